@@ -0,0 +1,61 @@
+#![feature(test)]
+extern crate test;
+
+use quicksockets::frame::WebsocketFrame;
+use test::{black_box, Bencher};
+
+const KEY: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+/// The byte at a time implementation masking used to go through.
+fn naive(data: &[u8], key: &[u8]) -> Vec<u8> {
+    data.iter()
+        .zip(key.iter().cycle())
+        .map(|(b, k)| b ^ k)
+        .collect()
+}
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|x| x as u8).collect()
+}
+
+#[bench]
+fn naive_small(b: &mut Bencher) {
+    let data = payload(100);
+    b.bytes = data.len() as u64;
+    b.iter(|| naive(black_box(&data), &KEY));
+}
+
+#[bench]
+fn in_place_small(b: &mut Bencher) {
+    let mut data = payload(100);
+    b.bytes = data.len() as u64;
+    b.iter(|| WebsocketFrame::mutate_in_place(black_box(&mut data), KEY, 0));
+}
+
+#[bench]
+fn naive_large(b: &mut Bencher) {
+    let data = payload(64 * 1024);
+    b.bytes = data.len() as u64;
+    b.iter(|| naive(black_box(&data), &KEY));
+}
+
+#[bench]
+fn in_place_large(b: &mut Bencher) {
+    let mut data = payload(64 * 1024);
+    b.bytes = data.len() as u64;
+    b.iter(|| WebsocketFrame::mutate_in_place(black_box(&mut data), KEY, 0));
+}
+
+#[bench]
+fn in_place_unaligned(b: &mut Bencher) {
+    let mut data = payload(64 * 1024 + 3);
+    b.bytes = data.len() as u64 - 3;
+    b.iter(|| WebsocketFrame::mutate_in_place(black_box(&mut data[3..]), KEY, 3));
+}
+
+#[bench]
+fn mutate_large(b: &mut Bencher) {
+    let data = payload(64 * 1024);
+    b.bytes = data.len() as u64;
+    b.iter(|| WebsocketFrame::mutate(black_box(&data), &KEY));
+}
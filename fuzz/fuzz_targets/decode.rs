@@ -0,0 +1,34 @@
+#![no_main]
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use quicksockets::{
+    config::Config,
+    deflate::{Deflate, DeflateConfig},
+    extension::Extension,
+    frame::WebsocketFrame,
+};
+use tokio_util::codec::Decoder;
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte decides whether the frames are inflated with permessage-deflate.
+    let (deflate, data) = match data.split_first() {
+        Some((x, rest)) => (x & 1 != 0, rest),
+        None => return,
+    };
+
+    let config = Config::default();
+    let mut extensions: Vec<Box<dyn Extension>> = Vec::new();
+
+    if deflate {
+        extensions.push(Box::new(Deflate::new(
+            DeflateConfig::default(),
+            Some(1 << 20),
+        )));
+    }
+
+    let mut codec = WebsocketFrame::with_config(&config, extensions, false);
+    let mut buf = BytesMut::from(data);
+
+    // Keep decoding until the input runs out or breaks the protocol, as `Framed` would.
+    while let Ok(Some(_)) = codec.decode(&mut buf) {}
+});
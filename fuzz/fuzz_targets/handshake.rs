@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use quicksockets::handshake::Handshake;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Handshake::parse(data);
+});
@@ -0,0 +1,62 @@
+#![no_main]
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use quicksockets::{
+    config::Config,
+    deflate::{Deflate, DeflateConfig},
+    extension::Extension,
+    frame::{Frame, Opcode, WebsocketFrame},
+};
+use tokio_util::codec::{Decoder, Encoder};
+
+fn codec(deflate: bool, client: bool) -> WebsocketFrame {
+    let mut extensions: Vec<Box<dyn Extension>> = Vec::new();
+
+    if deflate {
+        extensions.push(Box::new(Deflate::new(DeflateConfig::default(), None)));
+    }
+
+    WebsocketFrame::with_config(&Config::default(), extensions, client)
+}
+
+fuzz_target!(|data: &[u8]| {
+    // The first byte picks the kind of frame, the rest is its payload.
+    let (flags, payload) = match data.split_first() {
+        Some((x, rest)) => (*x, rest),
+        None => return,
+    };
+
+    let opcode = match flags & 0x03 {
+        0 => Opcode::Text,
+        1 => Opcode::Binary,
+        2 => Opcode::Ping,
+        _ => Opcode::Pong,
+    };
+
+    // Control frames can't be fragmented or carry more than 125 bytes.
+    let (fin, payload) = if opcode.is_control() {
+        (true, &payload[..payload.len().min(125)])
+    } else {
+        (flags & 0x04 != 0, payload)
+    };
+
+    let deflate = flags & 0x08 != 0;
+
+    // Frames have to come out of the other end the same way in both directions.
+    for &client in &[true, false] {
+        let mut encoder = codec(deflate, client);
+        let mut decoder = codec(deflate, !client);
+        let mut buf = BytesMut::new();
+
+        encoder
+            .encode(Frame::fragment(opcode, payload.to_vec(), fin), &mut buf)
+            .unwrap();
+
+        let frame = decoder.decode(&mut buf).unwrap().unwrap();
+
+        assert_eq!(frame.opcode, opcode);
+        assert_eq!(frame.is_final(), fin);
+        assert_eq!(&frame.payload()[..], payload);
+        assert!(buf.is_empty());
+    }
+});
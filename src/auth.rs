@@ -0,0 +1,99 @@
+//! Deciding which clients may open a connection before their upgrade is answered.
+//!
+//! ```rust no_run
+//! use quicksockets::{
+//!     auth::{Accept, Authorize},
+//!     prelude::*,
+//!     Request, Response, Websocket,
+//! };
+//!
+//! struct Tokens;
+//!
+//! #[async_trait]
+//! impl Authorize for Tokens {
+//!     async fn authorize(&self, request: &Request) -> Result<Accept, Response> {
+//!         match request.header("authorization") {
+//!             Some("Bearer secret") => Ok(Accept::new().identity("admin".to_string())),
+//!             _ => Err(Response::new(401).header("WWW-Authenticate", "Bearer")),
+//!         }
+//!     }
+//! }
+//!
+//! struct Example {
+//!     conn: Connection<TcpStream>,
+//! }
+//!
+//! #[async_trait]
+//! impl SocketCallback for Example {
+//!     async fn on_open(&mut self) {
+//!         let user = self.conn.identity::<String>().cloned().unwrap_or_default();
+//!         self.conn.send(Message::new(user)).await.unwrap();
+//!     }
+//!
+//!     async fn on_close(&mut self, _: Option<CloseCode>, _: String) {}
+//! }
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     Websocket::<TcpStream, _, _>::build("127.0.0.1:4545", |x| Example { conn: x })
+//!         .authorize(Tokens)
+//!         .listen()
+//!         .await;
+//! }
+//! ```
+use crate::handshake::{Request, Response};
+use async_trait::async_trait;
+use std::{any::Any, fmt, sync::Arc};
+
+/// Called with every upgrade request which is valid and matches a route, before the server
+/// answers it.
+#[async_trait]
+pub trait Authorize: Send + Sync {
+    /// Accepts the request or refuses it with `Err`, whose response is sent to the client before
+    /// the connection is closed.
+    async fn authorize(&self, request: &Request) -> Result<Accept, Response>;
+}
+
+/// Lets an upgrade go ahead.
+#[derive(Clone, Default)]
+pub struct Accept {
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) identity: Option<Arc<dyn Any + Send + Sync>>,
+}
+
+impl Accept {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a header, such as `Set-Cookie`, to the `101 Switching Protocols` response.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    /// Attaches who the client turned out to be to its connection, where handlers get it back
+    /// from `Connection::identity`.
+    pub fn identity<I: Any + Send + Sync>(mut self, identity: I) -> Self {
+        self.identity = Some(Arc::new(identity));
+        self
+    }
+}
+
+impl fmt::Debug for Accept {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Accept")
+            .field("headers", &self.headers)
+            .field("identity", &self.identity.is_some())
+            .finish()
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Authorizer(pub(crate) Arc<dyn Authorize>);
+
+impl fmt::Debug for Authorizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Authorizer")
+    }
+}
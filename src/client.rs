@@ -0,0 +1,232 @@
+//! The client side of the opening handshake.
+//!
+//! ```rust no_run
+//! use quicksockets::prelude::*;
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let mut conn = Connection::<TcpStream>::connect("ws://127.0.0.1:4545/chat")
+//!         .await
+//!         .unwrap();
+//!
+//!     conn.send(Message::from("hello")).await.unwrap();
+//!
+//!     while let Some(Ok(frame)) = conn.next().await {
+//!         println!("Server sent: {}", frame.get_msg());
+//!     }
+//! }
+//! ```
+use crate::{
+    config::Config,
+    connection::{accept_key, Connection},
+    frame::WebsocketFrame,
+    handshake, SslStream, TcpStream,
+};
+use httparse::Response;
+use native_tls::TlsConnector;
+use tokio::prelude::*;
+use tokio_util::codec::{Framed, FramedParts};
+
+/// The parts of a `ws://` or `wss://` URL needed to connect.
+struct Url {
+    secure: bool,
+    /// The host and port as written in the URL, for the `Host` header.
+    authority: String,
+    host: String,
+    port: u16,
+    /// The path and query, `/` when the URL has neither.
+    resource: String,
+}
+
+impl Url {
+    fn parse(url: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let (secure, rest) = if let Some(rest) = url.strip_prefix("ws://") {
+            (false, rest)
+        } else if let Some(rest) = url.strip_prefix("wss://") {
+            (true, rest)
+        } else {
+            return Err(format!("{} is not a ws:// or wss:// URL", url).into());
+        };
+
+        // Fragments have no meaning for websocket URLs.
+        let rest = rest.split('#').next().unwrap_or_default();
+
+        let (authority, resource) = match rest.find(|x| x == '/' || x == '?') {
+            Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+            Some(i) => (&rest[..i], rest[i..].to_string()),
+            None => (rest, "/".to_string()),
+        };
+
+        let default_port = if secure { 443 } else { 80 };
+
+        // IPv6 addresses are wrapped in brackets to tell their colons apart from the port.
+        let (host, port) = if let Some(rest) = authority.strip_prefix('[') {
+            match rest.find(']') {
+                Some(i) => {
+                    // Only a port may follow the closing bracket.
+                    let port = match &rest[i + 1..] {
+                        "" => None,
+                        x => Some(
+                            x.strip_prefix(':')
+                                .ok_or_else(|| format!("{} has an invalid port", url))?,
+                        ),
+                    };
+
+                    (&rest[..i], port)
+                }
+                None => return Err(format!("{} has an unterminated IPv6 address", url).into()),
+            }
+        } else {
+            match authority.rfind(':') {
+                Some(i) => (&authority[..i], Some(&authority[i + 1..])),
+                None => (authority, None),
+            }
+        };
+
+        if host.is_empty() {
+            return Err(format!("{} has no host", url).into());
+        }
+
+        let port = match port {
+            Some(x) => x
+                .parse()
+                .map_err(|_| format!("{} has an invalid port", url))?,
+            None => default_port,
+        };
+
+        Ok(Self {
+            secure,
+            authority: authority.to_string(),
+            host: host.to_string(),
+            port,
+            resource,
+        })
+    }
+}
+
+impl Connection<TcpStream> {
+    /// Connects to a `ws://` URL.
+    pub async fn connect(url: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let parsed = Url::parse(url)?;
+
+        if parsed.secure {
+            return Err(format!("{} needs a Connection<SslStream>", url).into());
+        }
+
+        let stream = TcpStream::connect((parsed.host.as_str(), parsed.port)).await?;
+        Self::client(stream, url).await
+    }
+}
+
+impl Connection<SslStream> {
+    /// Connects to a `wss://` URL, verifying the server's certificate against the system's
+    /// trusted roots.
+    pub async fn connect(url: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let parsed = Url::parse(url)?;
+
+        if !parsed.secure {
+            return Err(format!("{} needs a Connection<TcpStream>", url).into());
+        }
+
+        let stream = TcpStream::connect((parsed.host.as_str(), parsed.port)).await?;
+        let connector = tokio_tls::TlsConnector::from(TlsConnector::new()?);
+        let stream = connector.connect(&parsed.host, stream).await?;
+
+        Self::client(stream, url).await
+    }
+}
+
+impl<T: Unpin + AsyncRead + AsyncWrite + Send> Connection<T> {
+    /// Performs the client side of the opening handshake for `url` over an already established
+    /// stream. The returned connection masks every frame it sends.
+    pub async fn client(stream: T, url: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::client_with_config(stream, url, &Config::default()).await
+    }
+
+    pub async fn client_with_config(
+        mut stream: T,
+        url: &str,
+        config: &Config,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let url = Url::parse(url)?;
+        let key = base64::encode(&rand::random::<[u8; 16]>());
+
+        let req = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            url.resource, url.authority, key
+        );
+        stream.write_all(req.as_bytes()).await?;
+
+        let (head, rest) =
+            match handshake::read_head(&mut stream, config.max_handshake_size).await? {
+                Some(x) => x,
+                None => return Err("handshake response is too large".into()),
+            };
+
+        let mut headers = handshake::header_slots(&head);
+        let mut resp = Response::new(&mut headers);
+        resp.parse(&head)?;
+        Self::check_response(&resp, &key)?;
+
+        // The server may have sent its first frames right behind the response.
+        let mut parts = FramedParts::new(
+            stream,
+            WebsocketFrame::with_config(config, Vec::new(), true),
+        );
+        parts.read_buf = rest;
+
+        Ok(Self::from_framed(
+            Framed::from_parts(parts),
+            config,
+            Vec::new(),
+            None,
+        ))
+    }
+
+    /// Makes sure the server agreed to upgrade the connection.
+    fn check_response(resp: &Response, key: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if resp.code != Some(101) {
+            return Err(format!(
+                "server answered with {} instead of switching protocols",
+                resp.code.unwrap_or_default()
+            )
+            .into());
+        }
+
+        let header = |name: &str| {
+            resp.headers
+                .iter()
+                .find(|x| x.name.eq_ignore_ascii_case(name))
+                .map(|x| String::from_utf8_lossy(x.value).into_owned())
+        };
+
+        let upgrade = header("upgrade").unwrap_or_default();
+        let connection = header("connection").unwrap_or_default();
+
+        if !upgrade.eq_ignore_ascii_case("websocket") {
+            return Err("server didn't upgrade to websocket".into());
+        }
+
+        if !connection
+            .split(',')
+            .any(|x| x.trim().eq_ignore_ascii_case("upgrade"))
+        {
+            return Err("server didn't upgrade the connection".into());
+        }
+
+        if header("sec-websocket-accept") != Some(accept_key(key)) {
+            return Err("server sent the wrong Sec-WebSocket-Accept".into());
+        }
+
+        // We don't offer any, so the server can't have accepted any.
+        if header("sec-websocket-extensions").is_some() {
+            return Err("server accepted extensions that weren't offered".into());
+        }
+
+        if header("sec-websocket-protocol").is_some() {
+            return Err("server chose a subprotocol that wasn't offered".into());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+use crate::{
+    auth::Authorizer,
+    deflate::DeflateConfig,
+    extension::ExtensionFactory,
+    handshake::{OriginPolicy, ProtocolSelector},
+    http::HttpFallback,
+    router::Route,
+};
+use std::time::Duration;
+
+/// Settings shared by every connection accepted by a [`Websocket`] server.
+///
+/// The server builds its own, the builder methods of [`Websocket`] set the options below on it.
+/// Connections set up by hand, with [`Connection::with_config`] or
+/// [`Connection::client_with_config`], take one built from them directly.
+///
+/// [`Websocket`]: ../struct.Websocket.html
+/// [`Connection::with_config`]: ../connection/struct.Connection.html#method.with_config
+/// [`Connection::client_with_config`]: ../connection/struct.Connection.html#method.client_with_config
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// Outgoing messages with a bigger payload than this are sent as several fragments.
+    pub(crate) fragment_size: Option<usize>,
+    /// How often clients get pinged and how long they have to answer.
+    pub(crate) heartbeat: Option<(Duration, Duration)>,
+    /// How long to wait for the peer to answer our Close frame before hanging up on it.
+    pub(crate) close_timeout: Duration,
+    /// Biggest payload a single incoming frame may declare.
+    pub(crate) max_frame_size: Option<usize>,
+    /// Biggest incoming message once all of its fragments are joined.
+    pub(crate) max_message_size: Option<usize>,
+    /// Accept permessage-deflate when clients offer it.
+    pub(crate) deflate: Option<DeflateConfig>,
+    /// Custom extensions offered to clients, after permessage-deflate.
+    pub(crate) extensions: Vec<ExtensionFactory>,
+    /// Biggest opening handshake, request line and headers included, we are willing to read.
+    pub(crate) max_handshake_size: usize,
+    /// How long a client gets to complete the TLS and opening handshakes.
+    pub(crate) handshake_timeout: Duration,
+    /// Chooses the subprotocol spoken with each client.
+    pub(crate) protocol: Option<ProtocolSelector>,
+    /// Paths connections are accepted on when serving a [`Router`], any path otherwise.
+    ///
+    /// [`Router`]: ../router/struct.Router.html
+    pub(crate) routes: Option<Vec<Route>>,
+    /// Origins browsers may open connections from, any if unset.
+    pub(crate) origin: Option<OriginPolicy>,
+    /// Checks every client before its upgrade is accepted.
+    pub(crate) authorize: Option<Authorizer>,
+    /// Handlers for requests which don't ask for an upgrade, refused with 400 when there are none.
+    pub(crate) http: Vec<HttpFallback>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            fragment_size: None,
+            heartbeat: None,
+            close_timeout: Duration::from_secs(5),
+            max_frame_size: Some(16 << 20),
+            max_message_size: Some(64 << 20),
+            deflate: None,
+            extensions: Vec::new(),
+            max_handshake_size: 32 << 10,
+            handshake_timeout: Duration::from_secs(10),
+            protocol: None,
+            routes: None,
+            origin: None,
+            authorize: None,
+            http: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Splits outgoing messages into frames carrying at most `size` bytes of payload each.
+    pub fn fragment_size(mut self, size: usize) -> Self {
+        self.fragment_size = Some(size);
+        self
+    }
+
+    /// How long the peer gets to answer our Close frame before the connection is dropped.
+    pub fn close_timeout(mut self, timeout: Duration) -> Self {
+        self.close_timeout = timeout;
+        self
+    }
+
+    /// Fails the connection with `MsgTooBig` when the peer sends a frame with a payload bigger
+    /// than `size` bytes. Defaults to 16 MiB.
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.max_frame_size = Some(size);
+        self
+    }
+
+    /// Fails the connection with `MsgTooBig` when the peer sends a message, once all of its
+    /// fragments are joined, bigger than `size` bytes. Defaults to 64 MiB.
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.max_message_size = Some(size);
+        self
+    }
+
+    /// Gives up on opening handshakes bigger than `size` bytes. Defaults to 32 KiB.
+    pub fn max_handshake_size(mut self, size: usize) -> Self {
+        self.max_handshake_size = size;
+        self
+    }
+
+    /// Compresses messages with permessage-deflate for clients that offer it.
+    pub fn deflate(mut self, config: DeflateConfig) -> Self {
+        self.deflate = Some(config);
+        self
+    }
+}
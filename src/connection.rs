@@ -1,83 +1,699 @@
 use crate::{
-    frame::{Frame, WebsocketFrame},
+    auth::Accept,
+    config::Config,
+    deflate::Deflate,
+    error::{Error, HandshakeError},
+    extension::{self, Extension},
+    frame::{CloseCode, Frame, Opcode, WebsocketFrame},
+    handshake::{self, Handshake, Request, Response},
     message::Message,
 };
+use bytes::{Bytes, BytesMut};
 use crypto::{digest::Digest, sha1::Sha1};
-use futures::{lock::Mutex, SinkExt};
-use httparse::{Request, EMPTY_HEADER};
-use std::sync::Arc;
-use tokio::{prelude::*, stream::StreamExt};
-use tokio_util::codec::Framed;
+use futures::{
+    lock::{Mutex, MutexGuard},
+    stream::{SplitSink, SplitStream},
+    SinkExt,
+};
+use std::{
+    any::Any,
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{prelude::*, time};
+use tokio_util::codec::{Framed, FramedParts};
+
+type Sink<T> = SplitSink<Framed<T, WebsocketFrame>, Frame>;
 
 pub struct Connection<T: AsyncRead + AsyncWrite> {
-    stream: Arc<Mutex<Framed<T, WebsocketFrame>>>,
-    route: String,
+    /// The two halves of the stream are locked separately, so sending doesn't have to wait for
+    /// the peer to send something.
+    sink: Arc<Mutex<Sink<T>>>,
+    stream: Arc<Mutex<SplitStream<Framed<T, WebsocketFrame>>>>,
+    /// Fragments of a message which hasn't been completed yet.
+    partial: Arc<Mutex<Option<Partial>>>,
+    /// Set once either side has sent a Close frame.
+    closed: Arc<AtomicBool>,
+    /// Set once the peer's Close frame has been read.
+    answered: Arc<AtomicBool>,
+    /// Held by a [`MessageWriter`] for as long as it lives, so other messages wait for it. Set
+    /// while its message is unfinished, which it stays if the writer is dropped half way.
+    ///
+    /// [`MessageWriter`]: struct.MessageWriter.html
+    message: Arc<Mutex<bool>>,
+    close_timeout: Duration,
+    max_message_size: Option<usize>,
+    /// Names of the extensions negotiated during the handshake.
+    extensions: Vec<String>,
+    /// The subprotocol agreed on during the handshake.
+    protocol: Option<String>,
+    /// The upgrade request the connection was opened with.
+    request: Arc<Request>,
+    /// Parameters captured by the route the path matched.
+    params: HashMap<String, String>,
+    /// Whatever the authorization hook attached to the connection.
+    identity: Option<Arc<dyn Any + Send + Sync>>,
 }
 
 impl<T: Unpin + AsyncRead + AsyncWrite + Send> Clone for Connection<T> {
     fn clone(&self) -> Self {
         Self {
+            sink: Arc::clone(&self.sink),
             stream: Arc::clone(&self.stream),
-            route: self.route.clone(),
+            partial: Arc::clone(&self.partial),
+            closed: Arc::clone(&self.closed),
+            answered: Arc::clone(&self.answered),
+            message: Arc::clone(&self.message),
+            close_timeout: self.close_timeout,
+            max_message_size: self.max_message_size,
+            extensions: self.extensions.clone(),
+            protocol: self.protocol.clone(),
+            request: Arc::clone(&self.request),
+            params: self.params.clone(),
+            identity: self.identity.clone(),
         }
     }
 }
 
 impl<T: Unpin + AsyncRead + AsyncWrite + Send> Connection<T> {
-    pub async fn new(mut stream: T) -> Result<Self, Box<dyn std::error::Error>> {
-        Self::handshake(&mut stream).await?;
+    pub async fn new(stream: T) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::with_config(stream, &Config::default()).await
+    }
 
-        Ok(Self {
-            stream: Arc::new(Mutex::new(Framed::new(stream, WebsocketFrame))),
-            route: "/".into(),
-        })
+    /// Answers the opening handshake of a client on `stream`. Plain HTTP requests are answered by
+    /// the handlers in `config`, if there are any, and returned as an error as no connection comes
+    /// out of them.
+    pub async fn with_config(
+        stream: T,
+        config: &Config,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        match Self::accept(stream, config, None).await? {
+            Opened::Websocket(x) => Ok(x),
+            Opened::Http(mut stream, request) => {
+                Err(Self::serve_http(&mut stream, config, request).await)
+            }
+        }
+    }
+
+    /// Answers the opening handshake of a client connected from `peer_addr`. Plain HTTP requests
+    /// are handed back unanswered when there are handlers for them, so answering them isn't held
+    /// to the handshake timeout, see [`Connection::serve_http`].
+    ///
+    /// [`Connection::serve_http`]: struct.Connection.html#method.serve_http
+    pub(crate) async fn accept(
+        mut stream: T,
+        config: &Config,
+        peer_addr: Option<SocketAddr>,
+    ) -> Result<Opened<T>, Box<dyn std::error::Error>> {
+        let (head, rest) =
+            match handshake::read_head(&mut stream, config.max_handshake_size).await? {
+                Some(x) => x,
+                None => return Err(Self::reject(&mut stream, HandshakeError::TooLarge).await),
+            };
+
+        if !config.http.is_empty() {
+            if let Some(plain) = Request::parse(&head, peer_addr).filter(|x| !x.is_upgrade()) {
+                return Ok(Opened::Http(stream, plain));
+            }
+        }
+
+        let accepted = Self::handshake(&mut stream, config, peer_addr, &head, rest).await?;
+        let names = accepted
+            .extensions
+            .iter()
+            .map(|x| x.name().to_string())
+            .collect();
+
+        // Frames the client sent right behind its request are already in `rest`.
+        let codec = WebsocketFrame::with_config(config, accepted.extensions, false);
+        let mut parts = FramedParts::new(stream, codec);
+        parts.read_buf = accepted.rest;
+
+        let mut conn =
+            Self::from_framed(Framed::from_parts(parts), config, names, accepted.protocol);
+        conn.request = Arc::new(accepted.request);
+        conn.params = accepted.params;
+        conn.identity = accepted.identity;
+
+        Ok(Opened::Websocket(conn))
+    }
+
+    /// Wraps a stream which already went through the opening handshake.
+    pub(crate) fn from_framed(
+        stream: Framed<T, WebsocketFrame>,
+        config: &Config,
+        extensions: Vec<String>,
+        protocol: Option<String>,
+    ) -> Self {
+        let (sink, stream) = futures::StreamExt::split(stream);
+
+        Self {
+            sink: Arc::new(Mutex::new(sink)),
+            stream: Arc::new(Mutex::new(stream)),
+            partial: Arc::new(Mutex::new(None)),
+            closed: Arc::new(AtomicBool::new(false)),
+            answered: Arc::new(AtomicBool::new(false)),
+            message: Arc::new(Mutex::new(false)),
+            close_timeout: config.close_timeout,
+            max_message_size: config.max_message_size,
+            extensions,
+            protocol,
+            request: Arc::new(Request::new(None, "GET", "/", Vec::new())),
+            params: HashMap::new(),
+            identity: None,
+        }
     }
 
+    /// Returns the next frame sent by the client. Fragmented Text and Binary messages are joined
+    /// into a single frame before being returned, control frames that arrive in between the
+    /// fragments are returned straight away. Text messages are checked to be valid UTF-8 as each
+    /// fragment comes in.
+    ///
+    /// A Close frame from the client is answered and the stream shut down before it is returned,
+    /// after that no more frames are returned. Once we have sent a Close frame of our own, only the
+    /// answer to it is returned and everything else is discarded.
     // TODO: Impl the proper StreamExt trait instead of just proxying the calls
-    pub async fn next(&mut self) -> Option<Result<Frame, std::io::Error>> {
+    pub async fn next(&mut self) -> Option<Result<Frame, Error>> {
         let mut lock = self.stream.lock().await;
-        lock.next().await
+        let mut partial = self.partial.lock().await;
+
+        loop {
+            if self.answered.load(Ordering::SeqCst) {
+                return None;
+            }
+
+            let frame = match futures::StreamExt::next(&mut *lock).await? {
+                Ok(x) => x,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if self.closed.load(Ordering::SeqCst) && frame.opcode != Opcode::Close {
+                continue;
+            }
+
+            match frame.opcode {
+                Opcode::Close => {
+                    self.answered.store(true, Ordering::SeqCst);
+                    let mut sink = self.sink.lock().await;
+
+                    // Echo the status code back to complete the handshake, unless this is the
+                    // answer to our own Close frame, then hang up.
+                    if !self.closed.swap(true, Ordering::SeqCst) {
+                        let reply = match frame.code {
+                            Some(code) => Frame::close(code, ""),
+                            None => Ok(Frame::fragment(Opcode::Close, Bytes::new(), true)),
+                        };
+
+                        let sent = match reply {
+                            Ok(x) => sink.send(x).await,
+                            Err(e) => Err(e),
+                        };
+
+                        if let Err(e) = sent {
+                            return Some(Err(e.into()));
+                        }
+                    }
+
+                    if let Err(e) = sink.close().await {
+                        return Some(Err(e.into()));
+                    }
+
+                    return Some(Ok(frame));
+                }
+                x if x.is_control() => return Some(Ok(frame)),
+                Opcode::Continue => {
+                    let first = match partial.as_mut() {
+                        Some(x) => x,
+                        None => {
+                            return Some(Err(Error::Protocol(
+                                CloseCode::ProtocolError,
+                                "continuation frame without a message to continue".into(),
+                            )))
+                        }
+                    };
+
+                    if let Err(e) = self.check_size(first.data.len() + frame.payload().len()) {
+                        return Some(Err(e));
+                    }
+
+                    first.data.extend_from_slice(frame.payload());
+
+                    if let Err(e) = first.validate(frame.is_final()) {
+                        return Some(Err(e));
+                    }
+
+                    if frame.is_final() {
+                        return partial
+                            .take()
+                            .map(|x| Ok(x.frame.complete(x.data.freeze())));
+                    }
+                }
+                _ if partial.is_some() => {
+                    return Some(Err(Error::Protocol(
+                        CloseCode::ProtocolError,
+                        "new message started before the previous one was finished".into(),
+                    )))
+                }
+                _ => {
+                    if let Err(e) = self.check_size(frame.payload().len()) {
+                        return Some(Err(e));
+                    }
+
+                    let valid_utf8 = match frame.opcode {
+                        Opcode::Text => match validate_utf8(frame.payload(), 0, frame.is_final()) {
+                            Ok(x) => x,
+                            Err(e) => return Some(Err(e)),
+                        },
+                        _ => 0,
+                    };
+
+                    // Unfragmented messages are returned as they are, only the fragments of a
+                    // message that is still coming in have to be copied into one buffer.
+                    if frame.is_final() {
+                        return Some(Ok(frame));
+                    }
+
+                    *partial = Some(Partial {
+                        data: BytesMut::from(&frame.payload()[..]),
+                        valid_utf8,
+                        frame,
+                    });
+                }
+            }
+        }
     }
 
-    pub(crate) async fn handshake(stream: &mut T) -> Result<(), Box<dyn std::error::Error>> {
-        let mut buf: [u8; 1024] = [0; 1024];
-        stream.read(&mut buf).await?;
+    /// Answers the upgrade request in `head`, `rest` being whatever the client sent behind it.
+    pub(crate) async fn handshake(
+        stream: &mut T,
+        config: &Config,
+        peer_addr: Option<SocketAddr>,
+        head: &[u8],
+        rest: BytesMut,
+    ) -> Result<Accepted, Box<dyn std::error::Error>> {
+        let request = match Handshake::parse(head) {
+            Ok(x) => x,
+            Err(e) => return Err(Self::reject(stream, e).await),
+        };
 
-        let mut headers = [EMPTY_HEADER; 16];
-        let mut req = Request::new(&mut headers);
-        req.parse(&buf)?;
+        let details = Request::new(peer_addr, "GET", &request.resource, request.headers.clone());
 
-        let mut key: Vec<String> = req
-            .headers
-            .iter()
-            .filter(|x| x.name.to_lowercase() == "sec-websocket-key")
-            .map(|x| String::from_utf8_lossy(x.value).as_ref().to_string())
-            .collect();
+        if let Some(origin) = &config.origin {
+            if !origin.allows(details.header("origin")) {
+                return Err(Self::reject(stream, HandshakeError::Forbidden).await);
+            }
+        }
+
+        let params = match &config.routes {
+            Some(routes) => match routes.iter().find_map(|x| x.matches(details.path())) {
+                Some(x) => x,
+                None => return Err(Self::reject(stream, HandshakeError::NotFound).await),
+            },
+            None => HashMap::new(),
+        };
+
+        let accept = match &config.authorize {
+            Some(hook) => match hook.0.authorize(&details).await {
+                Ok(x) => x,
+                Err(e) => return Err(Self::reject(stream, HandshakeError::Rejected(e)).await),
+            },
+            None => Accept::default(),
+        };
+        let handshake = accept_key(&request.key);
+
+        let mut candidates: Vec<Box<dyn Extension>> = Vec::new();
+
+        if let Some(deflate) = &config.deflate {
+            candidates.push(Box::new(Deflate::new(
+                deflate.clone(),
+                config.max_message_size,
+            )));
+        }
+
+        candidates.extend(config.extensions.iter().map(|x| (x.0)()));
 
-        let mut hasher = Sha1::new();
+        let (extensions, response) = extension::negotiate(&request.extensions, candidates);
 
-        let key = key.pop().unwrap();
+        let protocol = config
+            .protocol
+            .as_ref()
+            .and_then(|x| x.select(&request.protocols));
 
-        hasher.input_str(format!("{}{}", key, "258EAFA5-E914-47DA-95CA-C5AB0DC85B11").as_ref());
-        let mut out_bytes = [0u8; 20];
-        hasher.result(&mut out_bytes);
+        let mut resp = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n", handshake);
 
-        let handshake = base64::encode(&out_bytes);
+        if let Some(response) = response {
+            resp.push_str(&format!("Sec-WebSocket-Extensions: {}\r\n", response));
+        }
+
+        if let Some(protocol) = &protocol {
+            resp.push_str(&format!("Sec-WebSocket-Protocol: {}\r\n", protocol));
+        }
 
-        let resp = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", handshake);
+        handshake::push_headers(&mut resp, &accept.headers);
+
+        resp.push_str("\r\n");
         stream.write_all(resp.as_bytes()).await?;
-        Ok(())
+        Ok(Accepted {
+            extensions,
+            protocol,
+            request: details,
+            params,
+            identity: accept.identity,
+            rest,
+        })
+    }
+
+    /// Answers a request which isn't an upgrade with the first HTTP handler which takes it, or 404
+    /// if none does, and hangs up. Returns the error a caller expecting an upgrade gets.
+    pub(crate) async fn serve_http(
+        stream: &mut T,
+        config: &Config,
+        request: Request,
+    ) -> Box<dyn std::error::Error> {
+        let mut response = None;
+
+        for handler in &config.http {
+            response = handler.0.handle(&request).await;
+
+            if response.is_some() {
+                break;
+            }
+        }
+
+        let response = response.unwrap_or_else(|| {
+            Response::new(404)
+                .header("Content-Type", "text/plain")
+                .body("not found")
+        });
+
+        let error = HandshakeError::NotWebsocket(response);
+        let response = error.response();
+
+        let out = if request.method() == "HEAD" {
+            response.head().into_bytes()
+        } else {
+            response.to_bytes()
+        };
+
+        let _ = stream.write_all(&out).await;
+        let _ = stream.shutdown().await;
+
+        error.into()
+    }
+
+    /// Answers a handshake we refuse with an HTTP error and hangs up.
+    async fn reject(stream: &mut T, error: HandshakeError) -> Box<dyn std::error::Error> {
+        let _ = stream.write_all(&error.response().to_bytes()).await;
+        let _ = stream.shutdown().await;
+
+        error.into()
+    }
+
+    /// Names of the extensions negotiated with the client, in the order they are applied to
+    /// outgoing frames.
+    pub fn extensions(&self) -> &[String] {
+        &self.extensions
+    }
+
+    /// The subprotocol agreed on with the client, if any.
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// Path the connection was opened on, without the query string.
+    pub fn route(&self) -> &str {
+        self.request.path()
     }
 
+    /// The upgrade request the client opened the connection with.
+    pub fn request(&self) -> &Request {
+        &self.request
+    }
+
+    /// The identity the authorization hook attached to the connection, if it is an `I`.
+    pub fn identity<I: Any>(&self) -> Option<&I> {
+        self.identity.as_ref()?.downcast_ref()
+    }
+
+    /// Value captured for `name` by the route the path matched, see [`Route`].
+    ///
+    /// [`Route`]: ../router/struct.Route.html
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    /// Sends a message, once any message being written by a [`MessageWriter`] is finished.
+    ///
+    /// [`MessageWriter`]: struct.MessageWriter.html
     pub async fn send(&mut self, m: Message) -> Result<(), std::io::Error> {
-        let mut lock = self.stream.lock().await;
+        let _message = self.message().await?;
+        let mut lock = self.sink.lock().await;
         lock.send(m.into()).await
     }
 
+    /// Sends a single frame. Control frames go out straight away, even in between the fragments
+    /// of a message, anything else waits like [`Connection::send`].
+    ///
+    /// [`Connection::send`]: struct.Connection.html#method.send
     pub async fn send_raw(&mut self, f: Frame) -> Result<(), std::io::Error> {
-        let mut lock = self.stream.lock().await;
+        let _message = if f.opcode.is_control() {
+            None
+        } else {
+            Some(self.message().await?)
+        };
+
+        let mut lock = self.sink.lock().await;
         lock.send(f).await
     }
 
-    pub async fn close() {}
+    /// Waits for the message being written by a [`MessageWriter`] to be finished, so another one
+    /// can be started. Fails if the writer was dropped before it finished its message, as the
+    /// peer still expects the rest of it.
+    ///
+    /// [`MessageWriter`]: struct.MessageWriter.html
+    async fn message(&self) -> Result<MutexGuard<'_, bool>, std::io::Error> {
+        let message = self.message.lock().await;
+
+        if *message {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                "a message writer was dropped before finishing its message",
+            ));
+        }
+
+        Ok(message)
+    }
+
+    fn check_size(&self, size: usize) -> Result<(), Error> {
+        match self.max_message_size {
+            Some(max) if size > max => Err(Error::Protocol(
+                CloseCode::MsgTooBig,
+                format!(
+                    "message of {} bytes is bigger than the limit of {}",
+                    size, max
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Starts a Text or Binary message which will be sent chunk by chunk as the data becomes
+    /// available instead of being buffered in memory. Other messages wait until the writer is
+    /// finished, control frames such as Pongs still go out in between its fragments and reading
+    /// carries on as usual.
+    ///
+    /// Control frames can't be fragmented, asking for a writer of any other opcode is an error, as
+    /// is asking for one once a Close frame has been sent.
+    pub async fn writer(&self, opcode: Opcode) -> Result<MessageWriter<'_, T>, std::io::Error> {
+        if opcode != Opcode::Text && opcode != Opcode::Binary {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("can't write a message as {:?} fragments", opcode),
+            ));
+        }
+
+        let message = self.message().await?;
+        self.check_open()?;
+
+        Ok(MessageWriter {
+            conn: self,
+            message,
+            opcode,
+        })
+    }
+
+    /// Fails once a Close frame has been sent, after which no more messages may follow.
+    fn check_open(&self) -> Result<(), std::io::Error> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotConnected,
+                "the connection is closing",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Closes the connection with `code` and a human readable `reason`. The Close frame is sent
+    /// and then we wait for the client to answer with its own Close frame before shutting the
+    /// stream down, anything else the client sends in the meantime is discarded. Clients that
+    /// don't answer within the close timeout are hung up on.
+    ///
+    /// Reasons are cut short to fit into the frame and codes which may not be sent are refused,
+    /// see [`Frame::close`].
+    ///
+    /// [`Frame::close`]: ../frame/struct.Frame.html#method.close
+    pub async fn close(&mut self, code: CloseCode, reason: &str) -> Result<(), Error> {
+        let frame = Frame::close(code, reason)?;
+
+        {
+            let mut sink = self.sink.lock().await;
+
+            if self.closed.swap(true, Ordering::SeqCst) {
+                return Ok(());
+            }
+
+            sink.send(frame).await?;
+        }
+
+        // Whoever is reading from the connection right now may get the answer instead of us, we
+        // only get to read once they are done.
+        let reply = async {
+            let mut lock = self.stream.lock().await;
+
+            while !self.answered.load(Ordering::SeqCst) {
+                match futures::StreamExt::next(&mut *lock).await {
+                    Some(frame) => {
+                        if frame?.opcode == Opcode::Close {
+                            self.answered.store(true, Ordering::SeqCst);
+                        }
+                    }
+                    None => break,
+                }
+            }
+
+            Ok::<(), Error>(())
+        };
+
+        let _ = time::timeout(self.close_timeout, reply).await;
+
+        // If `next` read the answer it has hung up already.
+        let _ = self.sink.lock().await.close().await;
+        Ok(())
+    }
+
+    /// Hangs up without a closing handshake, for clients which are gone or stopped answering.
+    pub(crate) async fn shutdown(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        let _ = self.sink.lock().await.close().await;
+    }
+}
+
+/// Computes the `Sec-WebSocket-Accept` value answering a `Sec-WebSocket-Key`.
+pub(crate) fn accept_key(key: &str) -> String {
+    let mut hasher = Sha1::new();
+    hasher.input_str(format!("{}{}", key, "258EAFA5-E914-47DA-95CA-C5AB0DC85B11").as_ref());
+
+    let mut out_bytes = [0u8; 20];
+    hasher.result(&mut out_bytes);
+
+    base64::encode(&out_bytes)
+}
+
+/// What came of a client's opening request.
+pub(crate) enum Opened<T: AsyncRead + AsyncWrite> {
+    /// The upgrade was accepted.
+    Websocket(Connection<T>),
+    /// A plain HTTP request, not answered yet.
+    Http(T, Request),
+}
+
+/// What the server agreed on with a client during the opening handshake.
+pub(crate) struct Accepted {
+    extensions: Vec<Box<dyn Extension>>,
+    protocol: Option<String>,
+    request: Request,
+    params: HashMap<String, String>,
+    identity: Option<Arc<dyn Any + Send + Sync>>,
+    /// Bytes the client sent right behind its request.
+    rest: BytesMut,
+}
+
+/// A message whose final fragment hasn't arrived yet.
+struct Partial {
+    /// The first fragment, carrying the opcode of the message.
+    frame: Frame,
+    /// The payload of all fragments received so far.
+    data: BytesMut,
+    /// How many bytes at the start of a text message are already known to be valid UTF-8.
+    valid_utf8: usize,
+}
+
+impl Partial {
+    /// Validates the part of a text message that hasn't been checked yet.
+    fn validate(&mut self, fin: bool) -> Result<(), Error> {
+        if self.frame.opcode == Opcode::Text {
+            self.valid_utf8 = validate_utf8(&self.data, self.valid_utf8, fin)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks `data` past the first `valid` bytes, which are already known to be valid UTF-8, and
+/// returns how many bytes are valid now. A character cut in half at the end is fine as long as
+/// more fragments are on the way.
+fn validate_utf8(data: &[u8], valid: usize, fin: bool) -> Result<usize, Error> {
+    match std::str::from_utf8(&data[valid..]) {
+        Ok(_) => Ok(data.len()),
+        Err(e) if e.error_len().is_none() && !fin => Ok(valid + e.valid_up_to()),
+        Err(_) => Err(Error::Protocol(
+            CloseCode::InvalidFramePayloadData,
+            "text message is not valid UTF-8".into(),
+        )),
+    }
+}
+
+/// Writes a single message as a series of fragments, see [`Connection::writer`].
+///
+/// The message is only complete once [`MessageWriter::finish`] is called. Dropping the writer
+/// after it has sent some of the message leaves the peer waiting for the rest, so no other
+/// message can be sent over the connection after that.
+///
+/// [`Connection::writer`]: struct.Connection.html#method.writer
+/// [`MessageWriter::finish`]: struct.MessageWriter.html#method.finish
+pub struct MessageWriter<'a, T: AsyncRead + AsyncWrite> {
+    conn: &'a Connection<T>,
+    /// Whether some of the message has been sent.
+    message: MutexGuard<'a, bool>,
+    opcode: Opcode,
+}
+
+impl<'a, T: Unpin + AsyncRead + AsyncWrite + Send> MessageWriter<'a, T> {
+    /// Sends `chunk` as the next fragment of the message.
+    pub async fn write(&mut self, chunk: &[u8]) -> Result<(), std::io::Error> {
+        self.conn.check_open()?;
+
+        let frame = Frame::fragment(self.opcode, Bytes::copy_from_slice(chunk), false);
+        self.opcode = Opcode::Continue;
+        *self.message = true;
+
+        self.conn.sink.lock().await.send(frame).await
+    }
+
+    /// Sends the final fragment, completing the message.
+    pub async fn finish(mut self) -> Result<(), std::io::Error> {
+        self.conn.check_open()?;
+
+        let frame = Frame::fragment(self.opcode, Bytes::new(), true);
+        self.conn.sink.lock().await.send(frame).await?;
+
+        *self.message = false;
+        Ok(())
+    }
 }
@@ -0,0 +1,268 @@
+//! The permessage-deflate extension as described in RFC 7692.
+use crate::{
+    error::Error,
+    extension::{Extension, ExtensionOffer},
+    frame::{CloseCode, Frame, Opcode},
+};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+/// Every compressed message ends with an empty stored block, which is left out on the wire.
+const TRAILER: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+/// How the server answers permessage-deflate offers from clients.
+#[derive(Clone, Debug, Default)]
+pub struct DeflateConfig {
+    /// Start every outgoing message with a fresh compression context. Uses less memory per
+    /// connection at the cost of a worse compression ratio.
+    pub server_no_context_takeover: bool,
+    /// Ask clients that support it to limit their LZ77 window to this many bits (8 to 15).
+    pub client_max_window_bits: Option<u8>,
+}
+
+/// Compression state of a single connection which negotiated permessage-deflate.
+pub struct Deflate {
+    config: DeflateConfig,
+    compress: Compress,
+    decompress: Decompress,
+    server_no_context_takeover: bool,
+    client_no_context_takeover: bool,
+    /// Compressed messages aren't allowed to inflate past this size.
+    max_message_size: Option<usize>,
+    /// Whether the incoming message currently being received is compressed.
+    inflating: bool,
+    /// Whether the outgoing message currently being sent is compressed.
+    deflating: bool,
+}
+
+impl Deflate {
+    pub fn new(config: DeflateConfig, max_message_size: Option<usize>) -> Self {
+        Self {
+            compress: Compress::new(Compression::default(), false),
+            decompress: Decompress::new(false),
+            server_no_context_takeover: config.server_no_context_takeover,
+            client_no_context_takeover: false,
+            config,
+            max_message_size,
+            inflating: false,
+            deflating: false,
+        }
+    }
+
+    /// Compresses one fragment of an outgoing message. The trailer is only stripped from the
+    /// final fragment, as the fragments together have to form a single deflate stream.
+    fn compress(&mut self, data: &[u8], fin: bool) -> Result<Vec<u8>, std::io::Error> {
+        let mut out = Vec::with_capacity(data.len() / 2 + 64);
+        let start = self.compress.total_in();
+
+        loop {
+            let consumed = (self.compress.total_in() - start) as usize;
+            if out.capacity() - out.len() < 64 {
+                out.reserve(out.capacity().max(64));
+            }
+
+            self.compress
+                .compress_vec(&data[consumed..], &mut out, FlushCompress::Sync)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+
+            let consumed = (self.compress.total_in() - start) as usize;
+            if consumed == data.len() && out.len() < out.capacity() {
+                break;
+            }
+        }
+
+        if fin {
+            if out.ends_with(&TRAILER) {
+                out.truncate(out.len() - TRAILER.len());
+            }
+
+            if self.server_no_context_takeover {
+                self.compress.reset();
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Decompresses one fragment of an incoming message. Fails with `MsgTooBig` once the output
+    /// grows past the message size limit, so a small frame can't be inflated into an arbitrary
+    /// amount of memory.
+    fn decompress(&mut self, data: &[u8], fin: bool) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::with_capacity(data.len() * 2 + 64);
+
+        // The sender strips the trailer off the end of the message, unless it ended the stream with
+        // a final block instead.
+        let ended = self.inflate(data, &mut out)?;
+        if fin && !ended {
+            self.inflate(&TRAILER, &mut out)?;
+        }
+
+        if fin && self.client_no_context_takeover {
+            self.decompress.reset(false);
+        }
+
+        Ok(out)
+    }
+
+    /// Feeds `input` to the decompressor, appending what comes out to `out`. Returns whether the
+    /// input ended the stream, in which case the decompressor is reset so the next message can
+    /// start a new one.
+    fn inflate(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<bool, Error> {
+        let start = self.decompress.total_in();
+
+        loop {
+            let consumed = (self.decompress.total_in() - start) as usize;
+            if out.capacity() - out.len() < 64 {
+                out.reserve(out.capacity().max(64));
+            }
+
+            let status = self
+                .decompress
+                .decompress_vec(&input[consumed..], out, FlushDecompress::Sync)
+                .map_err(|_| {
+                    Error::Protocol(
+                        CloseCode::InvalidFramePayloadData,
+                        "compressed message could not be inflated".into(),
+                    )
+                })?;
+
+            if let Some(max) = self.max_message_size {
+                if out.len() > max {
+                    return Err(Error::Protocol(
+                        CloseCode::MsgTooBig,
+                        format!("inflated message is bigger than the limit of {}", max),
+                    ));
+                }
+            }
+
+            if status == Status::StreamEnd {
+                self.decompress.reset(false);
+                return Ok(true);
+            }
+
+            let consumed = (self.decompress.total_in() - start) as usize;
+            if consumed == input.len() && out.len() < out.capacity() {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+fn window_bits(value: &str) -> Option<u8> {
+    match value.parse() {
+        Ok(x) if (8..=15).contains(&x) => Some(x),
+        _ => None,
+    }
+}
+
+impl Extension for Deflate {
+    fn name(&self) -> &str {
+        "permessage-deflate"
+    }
+
+    fn rsv_bits(&self) -> u8 {
+        0x40
+    }
+
+    fn negotiate(&mut self, offer: &ExtensionOffer) -> Option<String> {
+        let mut response = vec!["permessage-deflate".to_string()];
+        let mut server_no_context_takeover = self.config.server_no_context_takeover;
+        let mut client_no_context_takeover = false;
+        let mut client_max_window_bits = None;
+        let mut seen = Vec::new();
+
+        for (name, value) in &offer.params {
+            // Offers repeating a parameter have to be declined.
+            if seen.contains(&name) {
+                return None;
+            }
+            seen.push(name);
+
+            match (name.as_str(), value) {
+                ("server_no_context_takeover", None) => server_no_context_takeover = true,
+                ("client_no_context_takeover", None) => client_no_context_takeover = true,
+                // Our compressor always uses a 32KiB window, so we can only agree to 15 bits.
+                ("server_max_window_bits", Some(bits)) => match window_bits(bits)? {
+                    15 => response.push("server_max_window_bits=15".into()),
+                    _ => return None,
+                },
+                ("client_max_window_bits", bits) => {
+                    let offered = match bits {
+                        Some(bits) => window_bits(bits)?,
+                        None => 15,
+                    };
+
+                    client_max_window_bits =
+                        self.config.client_max_window_bits.map(|x| x.min(offered));
+                }
+                _ => return None,
+            }
+        }
+
+        if server_no_context_takeover {
+            response.push("server_no_context_takeover".into());
+        }
+
+        if client_no_context_takeover {
+            response.push("client_no_context_takeover".into());
+        }
+
+        if let Some(bits) = client_max_window_bits {
+            response.push(format!("client_max_window_bits={}", bits));
+        }
+
+        self.server_no_context_takeover = server_no_context_takeover;
+        self.client_no_context_takeover = client_no_context_takeover;
+
+        Some(response.join("; "))
+    }
+
+    fn encode(&mut self, frame: &mut Frame) -> Result<(), std::io::Error> {
+        if frame.opcode.is_control() {
+            return Ok(());
+        }
+
+        // Every message we send is compressed, the first frame is marked with RSV1.
+        if frame.opcode != Opcode::Continue {
+            self.deflating = true;
+            frame.set_rsv(frame.rsv() | 0x40);
+        }
+
+        if self.deflating {
+            let data = self.compress(frame.payload(), frame.is_final())?;
+            frame.set_payload(data);
+        }
+
+        if frame.is_final() {
+            self.deflating = false;
+        }
+
+        Ok(())
+    }
+
+    fn decode(&mut self, frame: &mut Frame) -> Result<(), Error> {
+        let rsv1 = frame.rsv() & 0x40 != 0;
+
+        match frame.opcode {
+            Opcode::Text | Opcode::Binary => self.inflating = rsv1,
+            _ if rsv1 => {
+                return Err(Error::Protocol(
+                    CloseCode::ProtocolError,
+                    "RSV1 is only allowed on the first frame of a message".into(),
+                ))
+            }
+            Opcode::Continue => {}
+            _ => return Ok(()),
+        }
+
+        if self.inflating {
+            let data = self.decompress(frame.payload(), frame.is_final())?;
+            frame.set_payload(data);
+        }
+
+        if frame.is_final() {
+            self.inflating = false;
+        }
+
+        Ok(())
+    }
+}
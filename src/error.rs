@@ -0,0 +1,109 @@
+use crate::{frame::CloseCode, handshake::Response};
+use std::fmt;
+
+/// Errors that can come up while reading from a websocket connection.
+#[derive(Debug)]
+pub enum Error {
+    /// The underlying stream failed.
+    Io(std::io::Error),
+    /// The peer broke the protocol and the connection has to be failed with the given close code.
+    Protocol(CloseCode, String),
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "io error: {}", e),
+            Self::Protocol(code, reason) => write!(f, "protocol error ({:?}): {}", code, reason),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Reasons for refusing a client's opening handshake. Each one is answered with an HTTP error
+/// response before the connection is closed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HandshakeError {
+    /// The request is malformed or isn't a valid websocket upgrade, answered with 400.
+    BadRequest(String),
+    /// The `Origin` of the request isn't allowed, answered with 403.
+    Forbidden,
+    /// No route matches the requested path, answered with 404.
+    NotFound,
+    /// The request doesn't use GET, answered with 405.
+    MethodNotAllowed,
+    /// The client asked for a websocket version other than 13, answered with 426.
+    UnsupportedVersion,
+    /// The request is bigger than the configured limit, answered with 431.
+    TooLarge,
+    /// The authorization hook refused the client, answered with the response it chose.
+    Rejected(Response),
+    /// A plain HTTP request, answered by the HTTP handlers of the server.
+    NotWebsocket(Response),
+}
+
+impl HandshakeError {
+    /// The HTTP status code the client is answered with.
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::BadRequest(_) => 400,
+            Self::Forbidden => 403,
+            Self::NotFound => 404,
+            Self::MethodNotAllowed => 405,
+            Self::UnsupportedVersion => 426,
+            Self::TooLarge => 431,
+            Self::Rejected(x) | Self::NotWebsocket(x) => x.status(),
+        }
+    }
+
+    /// The full HTTP response sent to the client.
+    pub fn response(&self) -> Response {
+        let response = match self {
+            // We hang up right after, so tell the client unless the handler already did.
+            Self::Rejected(x) | Self::NotWebsocket(x) => {
+                let mut x = x.clone();
+                if !x
+                    .headers()
+                    .iter()
+                    .any(|(name, _)| name.eq_ignore_ascii_case("connection"))
+                {
+                    x = x.header("Connection", "close");
+                }
+
+                return x;
+            }
+            Self::MethodNotAllowed => Response::new(405).header("Allow", "GET"),
+            Self::UnsupportedVersion => Response::new(426).header("Sec-WebSocket-Version", "13"),
+            _ => Response::new(self.status()),
+        };
+
+        response
+            .header("Connection", "close")
+            .header("Content-Type", "text/plain")
+            .body(self.to_string())
+    }
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadRequest(reason) => write!(f, "bad websocket handshake: {}", reason),
+            Self::Forbidden => write!(f, "origin not allowed"),
+            Self::NotFound => write!(f, "no websocket route for this path"),
+            Self::MethodNotAllowed => write!(f, "websocket handshakes have to use GET"),
+            Self::UnsupportedVersion => write!(f, "only websocket version 13 is supported"),
+            Self::TooLarge => write!(f, "websocket handshake is too large"),
+            Self::Rejected(x) => write!(f, "websocket handshake rejected with {}", x.status()),
+            Self::NotWebsocket(x) => write!(f, "plain HTTP request answered with {}", x.status()),
+        }
+    }
+}
+
+impl std::error::Error for HandshakeError {}
@@ -0,0 +1,124 @@
+//! Extensions negotiated through the `Sec-WebSocket-Extensions` header.
+//!
+//! An extension gets a chance to accept the client's offers during the handshake, claims some of
+//! the RSV bits and then transforms every frame going through the codec. Outgoing frames pass
+//! through the negotiated extensions in the order they were accepted, incoming frames in the
+//! reverse order.
+use crate::{error::Error, frame::Frame};
+use std::{fmt, sync::Arc};
+
+/// A single offer from the client's `Sec-WebSocket-Extensions` header.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExtensionOffer {
+    pub name: String,
+    pub params: Vec<(String, Option<String>)>,
+}
+
+impl ExtensionOffer {
+    /// Splits a `Sec-WebSocket-Extensions` header into its offers, in the client's order of
+    /// preference.
+    pub fn parse(header: &str) -> Vec<Self> {
+        header
+            .split(',')
+            .filter_map(|offer| {
+                let mut parts = offer.split(';').map(str::trim);
+                let name = parts.next().filter(|x| !x.is_empty())?.to_lowercase();
+
+                let params = parts
+                    .filter(|x| !x.is_empty())
+                    .map(|param| {
+                        let mut kv = param.splitn(2, '=');
+                        let key = kv.next().unwrap_or_default().trim().to_lowercase();
+                        let value = kv.next().map(|x| x.trim().trim_matches('"').to_string());
+
+                        (key, value)
+                    })
+                    .collect();
+
+                Some(Self { name, params })
+            })
+            .collect()
+    }
+}
+
+/// A websocket extension. A fresh instance is created for every connection so it can keep
+/// per-connection state, like a compression context.
+pub trait Extension: Send {
+    /// The extension token used in the `Sec-WebSocket-Extensions` header.
+    fn name(&self) -> &str;
+
+    /// The RSV bits this extension uses, as they appear in the first byte of a frame (`0x40`,
+    /// `0x20` or `0x10`). Two extensions claiming the same bit are never negotiated together.
+    fn rsv_bits(&self) -> u8 {
+        0
+    }
+
+    /// Called with the client's offers for this extension, most preferred first, until one is
+    /// accepted. Returns the element to add to the response header, usually the name of the
+    /// extension followed by the accepted parameters.
+    fn negotiate(&mut self, offer: &ExtensionOffer) -> Option<String>;
+
+    /// Transforms a frame before it is written to the stream.
+    fn encode(&mut self, frame: &mut Frame) -> Result<(), std::io::Error> {
+        let _ = frame;
+        Ok(())
+    }
+
+    /// Transforms a frame read from the stream before it reaches the connection.
+    fn decode(&mut self, frame: &mut Frame) -> Result<(), Error> {
+        let _ = frame;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for dyn Extension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Creates a new instance of an extension for every connection.
+#[derive(Clone)]
+pub(crate) struct ExtensionFactory(pub(crate) Arc<dyn Fn() -> Box<dyn Extension> + Send + Sync>);
+
+impl fmt::Debug for ExtensionFactory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ExtensionFactory")
+    }
+}
+
+/// Negotiates `candidates` against the client's `Sec-WebSocket-Extensions` header. Returns the
+/// accepted extensions along with the value of the response header, if anything was accepted.
+pub(crate) fn negotiate(
+    header: &str,
+    candidates: Vec<Box<dyn Extension>>,
+) -> (Vec<Box<dyn Extension>>, Option<String>) {
+    let offers = ExtensionOffer::parse(header);
+    let mut accepted = Vec::new();
+    let mut responses = Vec::new();
+    let mut used_bits = 0;
+
+    for mut extension in candidates {
+        if extension.rsv_bits() & used_bits != 0 {
+            continue;
+        }
+
+        let name = extension.name().to_string();
+        let response = offers
+            .iter()
+            .filter(|x| x.name == name)
+            .find_map(|x| extension.negotiate(x));
+
+        if let Some(response) = response {
+            used_bits |= extension.rsv_bits();
+            responses.push(response);
+            accepted.push(extension);
+        }
+    }
+
+    if responses.is_empty() {
+        (accepted, None)
+    } else {
+        (accepted, Some(responses.join(", ")))
+    }
+}
@@ -1,9 +1,13 @@
+use crate::{config::Config, error::Error, extension::Extension};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use bytes::{BytesMut, *};
-use std::io::Cursor;
+use std::{
+    convert::TryFrom,
+    io::{self, Cursor},
+};
 use tokio_util::codec::{Decoder, Encoder};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Opcode {
     Continue,
     Text,
@@ -14,6 +18,14 @@ pub enum Opcode {
     Other(u8),
 }
 
+impl Opcode {
+    /// Control frames (Close, Ping, Pong) can show up in the middle of a fragmented message.
+    pub fn is_control(&self) -> bool {
+        let opcode: u8 = (*self).into();
+        opcode & 0x08 != 0
+    }
+}
+
 impl From<u8> for Opcode {
     fn from(lh: u8) -> Self {
         match lh {
@@ -22,7 +34,7 @@ impl From<u8> for Opcode {
             0x02 => Self::Binary,
             0x08 => Self::Close,
             0x09 => Self::Ping,
-            0xA0 => Self::Pong,
+            0x0A => Self::Pong,
             _ => Self::Other(lh),
         }
     }
@@ -36,13 +48,13 @@ impl Into<u8> for Opcode {
             Self::Binary => 0x02,
             Self::Close => 0x08,
             Self::Ping => 0x09,
-            Self::Pong => 0xA0,
+            Self::Pong => 0x0A,
             Self::Other(other) => other,
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum CloseCode {
     NormalClosure,
     GoingAway,
@@ -59,6 +71,15 @@ pub enum CloseCode {
     Other(u16),
 }
 
+impl CloseCode {
+    /// Whether the code may be sent in a Close frame. 1005, 1006 and 1015 only exist to report
+    /// conditions locally and the rest of the range below 3000 is reserved.
+    pub fn is_allowed(&self) -> bool {
+        let code: u16 = (*self).into();
+        matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+    }
+}
+
 impl From<u16> for CloseCode {
     fn from(lh: u16) -> Self {
         match lh {
@@ -108,18 +129,29 @@ pub struct Frame {
     pub opcode: Opcode,
     masked: bool,
     length: u64,
-    pub reason: Option<u32>,
-    key: Vec<u8>,
-    data: Vec<u8>,
-    pub message: String,
+    /// Status code of a Close frame, the reason is the rest of the payload.
+    pub code: Option<CloseCode>,
+    data: Bytes,
 }
 
 impl Frame {
-    pub fn new(message: String) -> Self {
+    pub fn new<T: Into<Bytes>>(message: T) -> Self {
+        let data = message.into();
+
         Self {
-            message: message.clone(),
-            length: message.len() as u64,
-            data: message.as_bytes().to_vec(),
+            length: data.len() as u64,
+            data,
+            ..Default::default()
+        }
+    }
+
+    pub fn binary<T: Into<Bytes>>(data: T) -> Self {
+        let data = data.into();
+
+        Self {
+            opcode: Opcode::Binary,
+            length: data.len() as u64,
+            data,
             ..Default::default()
         }
     }
@@ -138,8 +170,114 @@ impl Frame {
         }
     }
 
+    /// Creates a Pong frame echoing back the payload of the Ping it answers.
+    pub fn pong_with<T: Into<Bytes>>(data: T) -> Self {
+        let data = data.into();
+
+        Self {
+            opcode: Opcode::Pong,
+            length: data.len() as u64,
+            data,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a Close frame carrying `code` and a human readable `reason`. A reason that doesn't
+    /// fit into a control frame is cut short at the last character boundary that does. Codes
+    /// which may not be sent, see [`CloseCode::is_allowed`], are refused.
+    ///
+    /// [`CloseCode::is_allowed`]: enum.CloseCode.html#method.is_allowed
+    pub fn close(code: CloseCode, reason: &str) -> Result<Self, io::Error> {
+        if !code.is_allowed() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} can't be sent in a close frame", code),
+            ));
+        }
+
+        let mut end = reason.len().min(MAX_CLOSE_REASON);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let reason = &reason[..end];
+
+        Ok(Self {
+            opcode: Opcode::Close,
+            code: Some(code),
+            length: reason.len() as u64 + 2,
+            data: Bytes::copy_from_slice(reason.as_bytes()),
+            ..Default::default()
+        })
+    }
+
+    /// Creates a single fragment of a message. The first fragment carries the opcode of the
+    /// message, every fragment after it must use `Opcode::Continue`.
+    pub fn fragment<T: Into<Bytes>>(opcode: Opcode, data: T, fin: bool) -> Self {
+        let data = data.into();
+
+        Self {
+            fin,
+            opcode,
+            length: data.len() as u64,
+            data,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the payload as a string. Invalid UTF-8 is replaced, text frames returned by
+    /// [`Connection::next`] have already been validated.
+    ///
+    /// [`Connection::next`]: ../connection/struct.Connection.html#method.next
     pub fn get_msg(&self) -> String {
-        self.message.clone()
+        String::from_utf8_lossy(&self.data).into_owned()
+    }
+
+    /// Returns the payload without copying it, cloning the returned `Bytes` is cheap.
+    pub fn payload(&self) -> &Bytes {
+        &self.data
+    }
+
+    /// Replaces the payload, used by extensions transforming frames.
+    pub fn set_payload<T: Into<Bytes>>(&mut self, data: T) {
+        self.data = data.into();
+        self.length = self.data.len() as u64;
+    }
+
+    /// Returns the RSV bits of the frame as they appear in its first byte.
+    pub fn rsv(&self) -> u8 {
+        let mut rsv = 0;
+        if self.rsv1 {
+            rsv |= 0x40;
+        }
+
+        if self.rsv2 {
+            rsv |= 0x20;
+        }
+
+        if self.rsv3 {
+            rsv |= 0x10;
+        }
+
+        rsv
+    }
+
+    pub fn set_rsv(&mut self, rsv: u8) {
+        self.rsv1 = rsv & 0x40 != 0;
+        self.rsv2 = rsv & 0x20 != 0;
+        self.rsv3 = rsv & 0x10 != 0;
+    }
+
+    pub fn is_final(&self) -> bool {
+        self.fin
+    }
+
+    /// Turns the first fragment of a message into the complete message once all of its payload
+    /// has been collected.
+    pub(crate) fn complete(mut self, data: Bytes) -> Self {
+        self.fin = true;
+        self.set_payload(data);
+        self
     }
 }
 
@@ -153,41 +291,167 @@ impl Default for Frame {
             opcode: Opcode::Text,
             masked: false,
             length: 0,
-            reason: None,
-            key: vec![0, 0, 0, 0],
-            data: vec![],
-            message: "".into(),
+            code: None,
+            data: Bytes::new(),
         }
     }
 }
 
-#[derive(Debug)]
-pub struct WebsocketFrame;
+#[derive(Debug, Default)]
+pub struct WebsocketFrame {
+    /// Outgoing data frames with a bigger payload than this are split into several fragments.
+    fragment_size: Option<usize>,
+    /// Incoming frames declaring a bigger payload than this are rejected before being buffered.
+    max_frame_size: Option<usize>,
+    /// Extensions negotiated during the handshake, in the order they were accepted.
+    extensions: Vec<Box<dyn Extension>>,
+    /// The RSV bits claimed by the negotiated extensions.
+    rsv_bits: u8,
+    /// Whether this is the client end of the connection, which masks the frames it sends and
+    /// expects unmasked frames from the server.
+    client: bool,
+}
+
+/// The longest reason that fits into a Close frame next to the status code.
+const MAX_CLOSE_REASON: usize = 123;
+
+/// A header alone never makes the decoder allocate more than this ahead of the payload arriving.
+const MAX_RESERVE: usize = 1 << 20;
 
 impl WebsocketFrame {
+    /// Creates the codec for a connection with the extensions negotiated for it. `client` is set
+    /// for the client end of a connection, which masks the frames it sends.
+    pub fn with_config(config: &Config, extensions: Vec<Box<dyn Extension>>, client: bool) -> Self {
+        Self {
+            fragment_size: config.fragment_size,
+            max_frame_size: config.max_frame_size,
+            rsv_bits: extensions.iter().fold(0, |acc, x| acc | x.rsv_bits()),
+            extensions,
+            client,
+        }
+    }
+
     pub fn mutate(data: &[u8], key: &[u8]) -> Vec<u8> {
-        data.iter()
-            .zip(key.iter().cycle())
-            .map(|(b, k)| b ^ k)
-            .collect()
+        let mut data = data.to_vec();
+        Self::mutate_in_place(&mut data, [key[0], key[1], key[2], key[3]], 0);
+        data
+    }
+
+    /// Masks or unmasks `data` in place. `offset` is the position of `data` within the payload,
+    /// so a payload can be masked chunk by chunk as it comes in.
+    ///
+    /// The bulk of the data is XORed a whole word at a time, only the unaligned bytes at either
+    /// end are done one by one.
+    pub fn mutate_in_place(data: &mut [u8], key: [u8; 4], offset: usize) {
+        let key = Self::rotate_key(key, offset);
+
+        // Every bit pattern is a valid u64, so viewing the aligned middle of the buffer as words
+        // is sound.
+        let (head, words, tail) = unsafe { data.align_to_mut::<u64>() };
+
+        for (i, b) in head.iter_mut().enumerate() {
+            *b ^= key[i & 3];
+        }
+
+        // Words are a multiple of the key length, so the key lines up the same way for all of
+        // them and for the tail after them.
+        let key = Self::rotate_key(key, head.len());
+        let word = u64::from_ne_bytes([
+            key[0], key[1], key[2], key[3], key[0], key[1], key[2], key[3],
+        ]);
+
+        for w in words.iter_mut() {
+            *w ^= word;
+        }
+
+        for (i, b) in tail.iter_mut().enumerate() {
+            *b ^= key[i & 3];
+        }
+    }
+
+    /// Returns the key as it applies to data starting `offset` bytes into the payload.
+    fn rotate_key(key: [u8; 4], offset: usize) -> [u8; 4] {
+        let mut key = key;
+        key.rotate_left(offset & 3);
+        key
+    }
+
+    /// Checks the frame header for anything RFC 6455 forbids the peer from sending. `length` is
+    /// the 7 bit length from the second byte, for control frames that is the whole length.
+    fn validate(
+        &self,
+        fin: bool,
+        rsv: u8,
+        opcode: Opcode,
+        masked: bool,
+        length: u8,
+    ) -> Result<(), Error> {
+        let reason = if rsv & !self.rsv_bits != 0 {
+            "reserved bits set without a negotiated extension"
+        } else if let Opcode::Other(_) = opcode {
+            "reserved opcode"
+        } else if !masked && !self.client {
+            "client frames must be masked"
+        } else if masked && self.client {
+            "server frames must not be masked"
+        } else if opcode.is_control() && !fin {
+            "fragmented control frame"
+        } else if opcode.is_control() && length > 125 {
+            "control frame payload longer than 125 bytes"
+        } else {
+            return Ok(());
+        };
+
+        Err(Error::Protocol(CloseCode::ProtocolError, reason.into()))
+    }
+
+    /// Splits the payload of a Close frame into the status code and the reason.
+    fn parse_close(payload: Bytes) -> Result<(Option<CloseCode>, Bytes), Error> {
+        match payload.len() {
+            0 => return Ok((None, payload)),
+            1 => {
+                return Err(Error::Protocol(
+                    CloseCode::ProtocolError,
+                    "close frame with a truncated status code".into(),
+                ))
+            }
+            _ => {}
+        }
+
+        let mut rdr = Cursor::new(&payload[..2]);
+        let code: CloseCode = rdr.read_u16::<BigEndian>()?.into();
+
+        if !code.is_allowed() {
+            return Err(Error::Protocol(
+                CloseCode::ProtocolError,
+                format!("close frame with the reserved status code {:?}", code),
+            ));
+        }
+
+        let reason = payload.slice(2..);
+
+        if std::str::from_utf8(&reason).is_err() {
+            return Err(Error::Protocol(
+                CloseCode::InvalidFramePayloadData,
+                "close reason is not valid UTF-8".into(),
+            ));
+        }
+
+        Ok((Some(code), reason))
     }
 }
 
 impl Decoder for WebsocketFrame {
     type Item = Frame;
-    type Error = std::io::Error;
+    type Error = Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let mut pos = 0;
         if src.len() < 2 {
             return Ok(None);
         }
 
-        let head = &src[..2];
-        pos += 2;
-
-        let first = head[0];
-        let second = head[1];
+        let first = src[0];
+        let second = src[1];
 
         let fin = first & 0x80 != 0;
         let rsv1 = first & 0x40 != 0;
@@ -196,46 +460,90 @@ impl Decoder for WebsocketFrame {
         let opcode: Opcode = (first & 0x0f).into();
         let masked = second & 0x80 != 0;
 
-        if !masked {
-            src.clear();
-            return Ok(None);
-        }
+        self.validate(fin, first & 0x70, opcode, masked, second & 0x7f)?;
 
-        let length = (second & 0x7f) as u64;
+        // Work out how long the header is before touching anything past the first two bytes, we
+        // may only have part of the frame buffered so far.
+        let (length, pos) = match second & 0x7f {
+            126 => {
+                if src.len() < 4 {
+                    return Ok(None);
+                }
 
-        let length = if length == 126 {
-            let mut rdr = Cursor::new(&src[2..4]);
-            pos += 2;
-            rdr.read_u16::<BigEndian>()? as u64
-        } else if length == 127 {
-            let mut rdr = Cursor::new(&src[2..10]);
-            pos += 8;
-            rdr.read_u64::<BigEndian>()? as u64
-        } else {
-            length
-        };
+                let mut rdr = Cursor::new(&src[2..4]);
+                (rdr.read_u16::<BigEndian>()? as u64, 4)
+            }
+            127 => {
+                if src.len() < 10 {
+                    return Ok(None);
+                }
 
-        let key = &src[pos..pos + 4];
-        pos += 4;
-
-        let reason = match opcode {
-            Opcode::Close => {
-                if length > 0 {
-                    let mut rdr = Cursor::new(&src[pos..pos + 2]);
-                    pos += 2;
-                    Some(rdr.read_u32::<BigEndian>()? as u32)
-                } else {
-                    None
+                let mut rdr = Cursor::new(&src[2..10]);
+                let length = rdr.read_u64::<BigEndian>()?;
+
+                // RFC 6455 5.2: the most significant bit of a 64-bit length must be 0.
+                if length >> 63 != 0 {
+                    return Err(Error::Protocol(
+                        CloseCode::ProtocolError,
+                        "frame length with the most significant bit set".into(),
+                    ));
                 }
+
+                (length, 10)
             }
-            _ => None,
+            length => (length as u64, 2),
         };
 
-        let data = &src[pos..pos + length as usize];
-        let decoded = WebsocketFrame::mutate(data, key);
-        let string_form = String::from_utf8_lossy(&decoded);
+        if let Some(max) = self.max_frame_size {
+            if length > max as u64 {
+                return Err(Error::Protocol(
+                    CloseCode::MsgTooBig,
+                    format!(
+                        "frame of {} bytes is bigger than the limit of {}",
+                        length, max
+                    ),
+                ));
+            }
+        }
 
-        let item = Some(Self::Item {
+        let header_len = if masked { pos + 4 } else { pos };
+        let frame_len = match usize::try_from(length)
+            .ok()
+            .and_then(|x| x.checked_add(header_len))
+        {
+            Some(x) => x,
+            None => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "frame length does not fit in memory",
+                )
+                .into())
+            }
+        };
+
+        if src.len() < frame_len {
+            src.reserve((frame_len - src.len()).min(MAX_RESERVE));
+            return Ok(None);
+        }
+
+        // Only consume the bytes belonging to this frame, anything after it is the start of the
+        // next frame and stays in the buffer for the next call. The payload is unmasked where it
+        // sits and handed out without being copied.
+        let mut decoded = src.split_to(frame_len).split_off(pos);
+        if masked {
+            let key = decoded.split_to(4);
+            let key = [key[0], key[1], key[2], key[3]];
+            WebsocketFrame::mutate_in_place(&mut decoded, key, 0);
+        }
+
+        let decoded = decoded.freeze();
+
+        let (code, data) = match opcode {
+            Opcode::Close => Self::parse_close(decoded)?,
+            _ => (None, decoded),
+        };
+
+        let mut frame = Self::Item {
             fin,
             rsv1,
             rsv2,
@@ -243,14 +551,15 @@ impl Decoder for WebsocketFrame {
             opcode,
             masked,
             length,
-            reason,
-            key: key.to_vec(),
-            data: decoded.clone(),
-            message: string_form.as_ref().to_string(),
-        });
-        src.clear();
-
-        Ok(item)
+            code,
+            data,
+        };
+
+        for extension in self.extensions.iter_mut().rev() {
+            extension.decode(&mut frame)?;
+        }
+
+        Ok(Some(frame))
     }
 }
 
@@ -258,10 +567,61 @@ impl Encoder for WebsocketFrame {
     type Item = Frame;
     type Error = std::io::Error;
 
-    // TODO: Add support for chunked frames.
     fn encode(&mut self, frame: Self::Item, buf: &mut BytesMut) -> Result<(), Self::Error> {
         let mut frame = frame;
-        let mut one = 0x80;
+        frame.masked = self.client;
+
+        // Close frames carry their status code in front of the reason.
+        if let Some(code) = frame.code.take() {
+            let mut data = BytesMut::with_capacity(frame.data.len() + 2);
+            data.put_u16(code.into());
+            data.extend_from_slice(&frame.data);
+            frame.data = data.freeze();
+        }
+
+        for extension in self.extensions.iter_mut() {
+            extension.encode(&mut frame)?;
+        }
+
+        match self.fragment_size {
+            // Control frames can't be fragmented, everything else gets split up into frames of at
+            // most `size` bytes with the first one carrying the opcode.
+            Some(size) if !frame.opcode.is_control() && frame.data.len() > size => {
+                let data = std::mem::take(&mut frame.data);
+                let mut chunks = data.chunks(size.max(1)).peekable();
+                let mut opcode = frame.opcode;
+
+                while let Some(chunk) = chunks.next() {
+                    let fin = frame.fin && chunks.peek().is_none();
+                    Self::write_frame(&frame, opcode, fin, chunk, buf)?;
+
+                    // Extensions only mark the first frame of a message.
+                    frame.rsv1 = false;
+                    frame.rsv2 = false;
+                    frame.rsv3 = false;
+                    opcode = Opcode::Continue;
+                }
+
+                Ok(())
+            }
+            _ => Self::write_frame(&frame, frame.opcode, frame.fin, &frame.data, buf),
+        }
+    }
+}
+
+impl WebsocketFrame {
+    fn write_frame(
+        frame: &Frame,
+        opcode: Opcode,
+        fin: bool,
+        data: &[u8],
+        buf: &mut BytesMut,
+    ) -> Result<(), std::io::Error> {
+        let mut one = 0u8;
+        if fin {
+            one |= 0x80;
+        }
+
         if frame.rsv1 {
             one |= 0x40;
         }
@@ -274,16 +634,15 @@ impl Encoder for WebsocketFrame {
             one |= 0x10;
         }
 
-        one |= frame.opcode.into(): u8;
+        one |= opcode.into(): u8;
 
         let mut two = 0u8;
-        frame.masked = false;
 
         if frame.masked {
             two |= 0x80;
         }
 
-        match frame.data.len() {
+        match data.len() {
             len if len < 126 => {
                 buf.reserve(2);
                 two |= len as u8;
@@ -300,25 +659,30 @@ impl Encoder for WebsocketFrame {
 
         buf.put_slice(&[one, two]);
 
-        if let Some(length_bytes) = match frame.data.len() {
+        if let Some(length_bytes) = match data.len() {
             len if len < 126 => None,
             len if len <= 65535 => Some(2),
             _ => Some(8),
         } {
             let mut rdr = Cursor::new(Vec::new());
             buf.reserve(length_bytes);
-            rdr.write_uint::<BigEndian>(frame.data.len() as u64, length_bytes)?;
+            rdr.write_uint::<BigEndian>(data.len() as u64, length_bytes)?;
             buf.put_slice(rdr.into_inner().as_ref());
         }
 
-        buf.reserve(frame.data.len());
+        buf.reserve(data.len());
 
         if frame.masked {
-            buf.reserve(frame.key.len());
-            buf.put_slice(&frame.key);
-            buf.put_slice(&WebsocketFrame::mutate(&frame.data, &frame.key));
+            // Clients pick a new unpredictable key for every frame they send.
+            let key: [u8; 4] = rand::random();
+
+            buf.reserve(key.len());
+            buf.put_slice(&key);
+            let start = buf.len();
+            buf.put_slice(data);
+            WebsocketFrame::mutate_in_place(&mut buf[start..], key, 0);
         } else {
-            buf.put_slice(&frame.data);
+            buf.put_slice(data);
         }
         Ok(())
     }
@@ -0,0 +1,483 @@
+//! Parsing and validation of the opening handshake sent by clients.
+use crate::error::HandshakeError;
+use bytes::BytesMut;
+use httparse::{Header, Status, EMPTY_HEADER};
+use std::{fmt, net::SocketAddr, sync::Arc};
+use tokio::prelude::*;
+
+/// The parts of a client's opening handshake the server needs to answer it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Handshake {
+    /// The request target, query string included.
+    pub resource: String,
+    /// The `Sec-WebSocket-Key` the `Sec-WebSocket-Accept` response is computed from.
+    pub key: String,
+    /// Every `Sec-WebSocket-Extensions` header joined into one list.
+    pub extensions: String,
+    /// The subprotocols offered in `Sec-WebSocket-Protocol`, in the client's order of preference.
+    pub protocols: Vec<String>,
+    /// Every header of the request, in the order they were sent.
+    pub headers: Vec<(String, String)>,
+}
+
+impl Handshake {
+    /// Parses the raw request sent by a client and checks it is a valid upgrade as described in
+    /// section 4.2.1 of RFC 6455.
+    pub fn parse(buf: &[u8]) -> Result<Self, HandshakeError> {
+        let bad = |reason: &str| HandshakeError::BadRequest(reason.into());
+
+        let mut headers = header_slots(buf);
+        let mut req = httparse::Request::new(&mut headers);
+
+        match req.parse(buf) {
+            Ok(Status::Complete(_)) => {}
+            Ok(Status::Partial) => return Err(bad("incomplete request")),
+            Err(e) => return Err(HandshakeError::BadRequest(e.to_string())),
+        }
+
+        if req.method != Some("GET") {
+            return Err(HandshakeError::MethodNotAllowed);
+        }
+
+        if req.version != Some(1) {
+            return Err(bad("HTTP/1.1 is required"));
+        }
+
+        let header = |name: &str| -> Vec<String> {
+            req.headers
+                .iter()
+                .filter(|x| x.name.eq_ignore_ascii_case(name))
+                .map(|x| String::from_utf8_lossy(x.value).into_owned())
+                .collect()
+        };
+
+        // Headers like Connection hold a comma separated list of tokens.
+        let has_token = |name: &str, token: &str| {
+            header(name)
+                .iter()
+                .flat_map(|x| x.split(','))
+                .any(|x| x.trim().eq_ignore_ascii_case(token))
+        };
+
+        if header("host").is_empty() {
+            return Err(bad("missing Host header"));
+        }
+
+        if !has_token("upgrade", "websocket") {
+            return Err(bad("missing Upgrade: websocket header"));
+        }
+
+        if !has_token("connection", "upgrade") {
+            return Err(bad("missing Connection: Upgrade header"));
+        }
+
+        if header("sec-websocket-version") != ["13"] {
+            return Err(HandshakeError::UnsupportedVersion);
+        }
+
+        let key = match header("sec-websocket-key").as_slice() {
+            [key] => key.trim().to_string(),
+            [] => return Err(bad("missing Sec-WebSocket-Key header")),
+            _ => return Err(bad("more than one Sec-WebSocket-Key header")),
+        };
+
+        // The key has to be 16 random bytes encoded in base64.
+        match base64::decode(&key) {
+            Ok(x) if x.len() == 16 => {}
+            _ => return Err(bad("Sec-WebSocket-Key is not 16 bytes of base64")),
+        }
+
+        let extensions = header("sec-websocket-extensions").join(",");
+
+        let protocols = header("sec-websocket-protocol")
+            .iter()
+            .flat_map(|x| x.split(','))
+            .map(str::trim)
+            .filter(|x| !x.is_empty())
+            .map(String::from)
+            .collect();
+
+        let headers = owned_headers(req.headers);
+
+        Ok(Self {
+            resource: req.path.unwrap_or("/").to_string(),
+            key,
+            extensions,
+            protocols,
+            headers,
+        })
+    }
+}
+
+/// The upgrade request a connection was opened with, kept around for handlers to authenticate
+/// clients, pick a tenant or log where they come from.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Request {
+    peer_addr: Option<SocketAddr>,
+    method: String,
+    path: String,
+    query: String,
+    query_params: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    pub(crate) fn new(
+        peer_addr: Option<SocketAddr>,
+        method: &str,
+        resource: &str,
+        headers: Vec<(String, String)>,
+    ) -> Self {
+        let mut parts = resource.splitn(2, '?');
+        let path = parts.next().unwrap_or_default().to_string();
+        let query = parts.next().unwrap_or_default().to_string();
+
+        let query_params = query
+            .split('&')
+            .filter(|x| !x.is_empty())
+            .map(|x| {
+                let mut pair = x.splitn(2, '=');
+                let name = percent_decode(pair.next().unwrap_or_default());
+                let value = percent_decode(pair.next().unwrap_or_default());
+                (name, value)
+            })
+            .collect();
+
+        Self {
+            peer_addr,
+            method: method.to_string(),
+            path,
+            query,
+            query_params,
+            headers,
+        }
+    }
+
+    /// Parses any HTTP request, upgrade or not, without validating it.
+    pub(crate) fn parse(buf: &[u8], peer_addr: Option<SocketAddr>) -> Option<Self> {
+        let mut headers = header_slots(buf);
+        let mut req = httparse::Request::new(&mut headers);
+
+        match req.parse(buf) {
+            Ok(Status::Complete(_)) => {}
+            _ => return None,
+        }
+
+        let headers = owned_headers(req.headers);
+
+        Some(Self::new(peer_addr, req.method?, req.path?, headers))
+    }
+
+    /// Whether the client asks for its connection to be upgraded to a websocket.
+    pub(crate) fn is_upgrade(&self) -> bool {
+        self.headers
+            .iter()
+            .filter(|(x, _)| x.eq_ignore_ascii_case("upgrade"))
+            .flat_map(|(_, value)| value.split(','))
+            .any(|x| x.trim().eq_ignore_ascii_case("websocket"))
+    }
+
+    /// Address of the client, unknown for connections not accepted by a [`Websocket`] server.
+    ///
+    /// [`Websocket`]: ../struct.Websocket.html
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// The HTTP method, always `GET` for websocket connections.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Path of the request target, without the query string.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The query string as sent, without the leading `?`.
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// Every parameter of the query string, percent-decoded, in the order they were sent.
+    pub fn query_params(&self) -> &[(String, String)] {
+        &self.query_params
+    }
+
+    /// The first value of the query parameter `name`.
+    pub fn query_param(&self, name: &str) -> Option<&str> {
+        self.query_params
+            .iter()
+            .find(|(x, _)| x == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every header of the request, in the order they were sent.
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// The first value of the header `name`, compared case-insensitively.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(x, _)| x.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every cookie sent in `Cookie` headers, values as sent.
+    pub fn cookies(&self) -> Vec<(&str, &str)> {
+        self.headers
+            .iter()
+            .filter(|(x, _)| x.eq_ignore_ascii_case("cookie"))
+            .flat_map(|(_, value)| value.split(';'))
+            .filter_map(|x| {
+                let mut pair = x.trim().splitn(2, '=');
+                let name = pair.next().filter(|x| !x.is_empty())?;
+                Some((name, pair.next().unwrap_or_default()))
+            })
+            .collect()
+    }
+
+    /// The value of the cookie `name`.
+    pub fn cookie(&self, name: &str) -> Option<&str> {
+        self.cookies()
+            .into_iter()
+            .find(|(x, _)| *x == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// An HTTP response sent to a client instead of upgrading its connection.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    /// An empty response with the given status code.
+    pub fn new(status: u16) -> Self {
+        Self {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Adds a header. `Content-Length` is always set from the body and shouldn't be added.
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn body<B: Into<Vec<u8>>>(mut self, body: B) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    pub fn headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+
+    /// The response as it is written to the stream.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = self.head().into_bytes();
+        out.extend_from_slice(&self.body);
+        out
+    }
+
+    /// Status line and headers, all a `HEAD` request is answered with.
+    pub(crate) fn head(&self) -> String {
+        let mut head = format!("HTTP/1.1 {} {}\r\n", self.status, reason(self.status));
+
+        push_headers(&mut head, &self.headers);
+        head.push_str(&format!("Content-Length: {}\r\n\r\n", self.body.len()));
+        head
+    }
+}
+
+/// Appends `headers` to the head of a response, leaving out any which would break it.
+pub(crate) fn push_headers(head: &mut String, headers: &[(String, String)]) {
+    for (name, value) in headers {
+        let valid = !name.is_empty()
+            && !name.contains(|x: char| x == ':' || x.is_whitespace())
+            && !value.contains(|x: char| x == '\r' || x == '\n');
+
+        if valid {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+    }
+}
+
+/// The reason phrase of the status codes a websocket server is likely to answer with.
+fn reason(status: u16) -> &'static str {
+    match status {
+        101 => "Switching Protocols",
+        200 => "OK",
+        204 => "No Content",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "",
+    }
+}
+
+/// Decodes `%XX` escapes and `+` the way browsers encode query strings. Invalid escapes are kept
+/// as they are.
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = s
+            .get(i + 1..i + 3)
+            .filter(|x| x.bytes().all(|x| x.is_ascii_hexdigit()))
+            .and_then(|x| u8::from_str_radix(x, 16).ok());
+
+        match (bytes[i], escaped) {
+            (b'%', Some(x)) => {
+                out.push(x);
+                i += 2;
+            }
+            (b'+', _) => out.push(b' '),
+            (x, _) => out.push(x),
+        }
+
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Picks the subprotocol to speak out of the ones a client offers, if any.
+#[derive(Clone)]
+pub(crate) struct ProtocolSelector(
+    pub(crate) Arc<dyn Fn(&[String]) -> Option<String> + Send + Sync>,
+);
+
+impl ProtocolSelector {
+    /// Only a protocol the client actually offered can be chosen.
+    pub(crate) fn select(&self, offered: &[String]) -> Option<String> {
+        (self.0)(offered).filter(|x| offered.contains(x))
+    }
+}
+
+impl fmt::Debug for ProtocolSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ProtocolSelector")
+    }
+}
+
+/// Decides which `Origin`s may open connections.
+#[derive(Clone)]
+pub(crate) struct OriginPolicy(pub(crate) Arc<dyn Fn(&str) -> bool + Send + Sync>);
+
+impl OriginPolicy {
+    /// Only browsers send an `Origin`, and only they can be made to open a socket carrying a
+    /// victim's cookies by another site, so requests without one are let through.
+    pub(crate) fn allows(&self, origin: Option<&str>) -> bool {
+        origin.map_or(true, |x| (self.0)(&x.to_ascii_lowercase()))
+    }
+}
+
+impl fmt::Debug for OriginPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("OriginPolicy")
+    }
+}
+
+/// Matches a lowercase `origin` against an exact origin such as `https://example.com`, or one
+/// with a wildcard for the subdomain such as `https://*.example.com`.
+pub(crate) fn origin_matches(pattern: &str, origin: &str) -> bool {
+    let i = match pattern.find("://*.") {
+        Some(x) => x,
+        None => return pattern == origin,
+    };
+
+    let (scheme, domain) = (&pattern[..i + 3], &pattern[i + 4..]);
+
+    if origin.len() <= scheme.len() + domain.len()
+        || !origin.starts_with(scheme)
+        || !origin.ends_with(domain)
+    {
+        return false;
+    }
+
+    origin[scheme.len()..origin.len() - domain.len()]
+        .chars()
+        .all(|x| x.is_ascii_alphanumeric() || x == '-' || x == '.')
+}
+
+/// Copies the headers parsed by httparse out of the buffer they point into.
+fn owned_headers(headers: &[Header]) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|x| {
+            let value = String::from_utf8_lossy(x.value).into_owned();
+            (x.name.to_string(), value)
+        })
+        .collect()
+}
+
+/// Room for every header in `buf`, there can't be more of them than there are lines.
+pub(crate) fn header_slots(buf: &[u8]) -> Vec<Header<'_>> {
+    vec![EMPTY_HEADER; buf.windows(2).filter(|x| *x == b"\r\n").count()]
+}
+
+/// Reads the head of an HTTP request or response from `stream`, up to and including the empty
+/// line ending it. Returns the head along with anything read past it, which already belongs to
+/// the websocket connection, or `None` if the head is bigger than `limit`.
+pub(crate) async fn read_head<T: AsyncRead + Unpin>(
+    stream: &mut T,
+    limit: usize,
+) -> Result<Option<(BytesMut, BytesMut)>, std::io::Error> {
+    let mut buf = BytesMut::with_capacity(1024);
+    let mut searched = 0;
+
+    loop {
+        if let Some(i) = buf[searched..].windows(4).position(|x| x == b"\r\n\r\n") {
+            let end = searched + i + 4;
+
+            if end > limit {
+                break;
+            }
+
+            let rest = buf.split_off(end);
+            return Ok(Some((buf, rest)));
+        }
+
+        if buf.len() >= limit {
+            break;
+        }
+
+        // The end of the head may be split between this read and the next one.
+        searched = buf.len().saturating_sub(3);
+
+        buf.reserve(1024);
+        if stream.read_buf(&mut buf).await? == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "connection closed during the handshake",
+            ));
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,155 @@
+//! Answering plain HTTP requests, the ones which don't ask for an upgrade, on the same port as
+//! the websocket server. This is what load balancer health checks and browsers opening the URL
+//! of a socket directly send.
+//!
+//! Handlers are tried in the order they were added to the server, the first one returning a
+//! response answers the request. Requests none of them answer get a 404. Either way the
+//! connection is closed once the response is sent.
+//!
+//! ```rust no_run
+//! use quicksockets::{
+//!     http::{Health, StaticFile},
+//!     prelude::*,
+//!     Websocket,
+//! };
+//!
+//! struct Example {
+//!     conn: Connection<TcpStream>,
+//! }
+//!
+//! #[async_trait]
+//! impl SocketCallback for Example {
+//!     async fn on_close(&mut self, _: Option<CloseCode>, _: String) {}
+//! }
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     Websocket::<TcpStream, _, _>::build("127.0.0.1:4545", |x| Example { conn: x })
+//!         .http(Health::default())
+//!         .http(StaticFile::new("/", "index.html"))
+//!         .listen()
+//!         .await;
+//! }
+//! ```
+use crate::handshake::{Request, Response};
+use async_trait::async_trait;
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[async_trait]
+pub trait HttpHandler: Send + Sync {
+    /// Answers `request`, or returns `None` to leave it to the next handler.
+    async fn handle(&self, request: &Request) -> Option<Response>;
+}
+
+/// Answers `GET` and `HEAD` requests for `/health`, or another path, with `200 OK`.
+#[derive(Clone, Debug)]
+pub struct Health {
+    path: String,
+}
+
+impl Health {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+        }
+    }
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self::new("/health")
+    }
+}
+
+#[async_trait]
+impl HttpHandler for Health {
+    async fn handle(&self, request: &Request) -> Option<Response> {
+        if request.path() != self.path || !readable(request) {
+            return None;
+        }
+
+        let response = Response::new(200)
+            .header("Content-Type", "text/plain")
+            .header("Cache-Control", "no-store")
+            .body("OK");
+
+        Some(response)
+    }
+}
+
+/// Answers `GET` and `HEAD` requests for `path` with the contents of `file`, read each time it is
+/// requested. A file which can't be read is answered with 500.
+#[derive(Clone, Debug)]
+pub struct StaticFile {
+    path: String,
+    file: PathBuf,
+}
+
+impl StaticFile {
+    pub fn new<P: Into<PathBuf>>(path: &str, file: P) -> Self {
+        Self {
+            path: path.to_string(),
+            file: file.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl HttpHandler for StaticFile {
+    async fn handle(&self, request: &Request) -> Option<Response> {
+        if request.path() != self.path || !readable(request) {
+            return None;
+        }
+
+        let response = match tokio::fs::read(&self.file).await {
+            Ok(x) => Response::new(200)
+                .header("Content-Type", content_type(&self.file))
+                .body(x),
+            Err(_) => Response::new(500)
+                .header("Content-Type", "text/plain")
+                .body("couldn't read the file"),
+        };
+
+        Some(response)
+    }
+}
+
+fn readable(request: &Request) -> bool {
+    request.method() == "GET" || request.method() == "HEAD"
+}
+
+/// Guesses the `Content-Type` of the files a small test page is likely made of.
+fn content_type(file: &Path) -> &'static str {
+    let extension = file
+        .extension()
+        .and_then(|x| x.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    match extension.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" => "application/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "ico" => "image/x-icon",
+        "wasm" => "application/wasm",
+        _ => "application/octet-stream",
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct HttpFallback(pub(crate) Arc<dyn HttpHandler>);
+
+impl fmt::Debug for HttpFallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("HttpFallback")
+    }
+}
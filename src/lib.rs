@@ -1,7 +1,7 @@
 //! Quicksockets
 //! Quicksockets is a purely async implementation of Websockets as described in RFC6455. This library
-//! currently only comes with a server implementation but a client one is planned. The server
-//! supports SSL and Raw TCP.
+//! comes with both a server and a client implementation, both support SSL and Raw TCP. See the
+//! [`client`] module for connecting to a server.
 //!
 //! # Example
 //! To use quicksockets you must create a handler struct which implements the [`SocketCallback`]
@@ -91,34 +91,60 @@
 //! }
 //! ```
 //!
+//! [`client`]: client/index.html
 //! [`SocketCallback`]: trait.SocketCallback.html
 //! [`TcpStream`]: type.TcpStream.html
 //! [`SslStream`]: type.SslStream.html
 #![feature(type_ascription)]
+pub mod auth;
+pub mod client;
+pub mod config;
 pub mod connection;
+pub mod deflate;
+pub mod error;
+pub mod extension;
 pub mod frame;
+pub mod handshake;
+pub mod http;
 pub mod message;
+pub mod router;
 pub mod streams;
 
 use crate::{
-    frame::Opcode,
+    auth::{Authorize, Authorizer},
+    config::Config,
+    deflate::DeflateConfig,
+    error::Error,
+    extension::{Extension, ExtensionFactory},
+    frame::{CloseCode, Frame, Opcode},
+    handshake::{OriginPolicy, ProtocolSelector},
+    http::{HttpFallback, HttpHandler},
+    router::{Dispatch, Handler, Router},
     streams::{ssl, tcp, Stream},
 };
 use async_trait::async_trait;
-use connection::Connection;
-use futures::executor::block_on;
+use connection::{Connection, Opened};
+use futures::{
+    executor::block_on,
+    future::{self, Either},
+    pin_mut,
+};
 use message::Message;
 use native_tls::{Identity, TlsAcceptor};
-use std::{fs::File, io::Read, net::SocketAddr, sync::Arc};
-use tokio::net;
+use std::{fs::File, io::Read, net::SocketAddr, sync::Arc, time::Duration};
 use tokio::prelude::{AsyncRead, AsyncWrite};
+use tokio::{
+    net,
+    time::{self, Instant},
+};
 use tokio_tls::TlsStream;
 
 /// This module contains all the essential imports a quicksockets app may need. This should be
 /// included as a prelude in any project using quicksockets.
 pub mod prelude {
     pub use super::{
-        connection::Connection, message::Message, SocketCallback, SslStream, TcpStream,
+        connection::Connection, error::Error, frame::CloseCode, message::Message, SocketCallback,
+        SslStream, TcpStream,
     };
     pub use async_trait::async_trait;
     pub use tokio::prelude::{AsyncRead, AsyncWrite};
@@ -129,23 +155,42 @@ pub type TcpStream = net::TcpStream;
 /// Describes a SSL encrypted Connection Stream. If this is used, all traffic will be encrypted.
 pub type SslStream = tokio_tls::TlsStream<TcpStream>;
 
-pub struct Request {
-    pub id: String,
-    pub resource: String,
-    pub message: String,
-}
-
-pub struct Response {
-    pub message: String,
-}
+pub use handshake::{Request, Response};
 
 #[async_trait]
 pub trait SocketCallback {
     async fn on_open(&mut self) {}
-    async fn on_close(&mut self, close_code: Option<u32>, reason: String);
+    async fn on_close(&mut self, close_code: Option<CloseCode>, reason: String);
     async fn on_message(&mut self, frame: Message) {
         let _ = frame;
     }
+    /// Called when the client breaks the protocol or the stream fails, right before the
+    /// connection is closed.
+    async fn on_error(&mut self, error: &Error) {
+        let _ = error;
+    }
+}
+
+/// Lets boxed handlers, like the ones a [`Router`] creates, be served like any other.
+///
+/// [`Router`]: router/struct.Router.html
+#[async_trait]
+impl<S: SocketCallback + Send + ?Sized> SocketCallback for Box<S> {
+    async fn on_open(&mut self) {
+        (**self).on_open().await
+    }
+
+    async fn on_close(&mut self, close_code: Option<CloseCode>, reason: String) {
+        (**self).on_close(close_code, reason).await
+    }
+
+    async fn on_message(&mut self, frame: Message) {
+        (**self).on_message(frame).await
+    }
+
+    async fn on_error(&mut self, error: &Error) {
+        (**self).on_error(error).await
+    }
 }
 
 pub struct Websocket<T, R, F>
@@ -156,6 +201,294 @@ where
 {
     callback: Arc<R>,
     sock: Box<dyn Stream<Out = T>>,
+    config: Config,
+}
+
+impl<T, R, F> Websocket<T, R, F>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    R: (Fn(Connection<T>) -> F) + Send + Sync + 'static,
+    F: SocketCallback + Send + Sync + 'static,
+{
+    /// Sets [`Config::fragment_size`] for every connection.
+    ///
+    /// [`Config::fragment_size`]: config/struct.Config.html#method.fragment_size
+    pub fn fragment_size(mut self, size: usize) -> Self {
+        self.config = self.config.fragment_size(size);
+        self
+    }
+
+    /// Pings every client each `interval` and drops the ones that don't answer with a Pong
+    /// within `timeout`. Dropped clients are reported to `on_close` with `AbnormalClosure`.
+    pub fn heartbeat(mut self, interval: Duration, timeout: Duration) -> Self {
+        self.config.heartbeat = Some((interval, timeout));
+        self
+    }
+
+    /// Sets [`Config::close_timeout`] for every connection.
+    ///
+    /// [`Config::close_timeout`]: config/struct.Config.html#method.close_timeout
+    pub fn close_timeout(mut self, timeout: Duration) -> Self {
+        self.config = self.config.close_timeout(timeout);
+        self
+    }
+
+    /// Sets [`Config::max_frame_size`] for every connection.
+    ///
+    /// [`Config::max_frame_size`]: config/struct.Config.html#method.max_frame_size
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.config = self.config.max_frame_size(size);
+        self
+    }
+
+    /// Sets [`Config::max_message_size`] for every connection.
+    ///
+    /// [`Config::max_message_size`]: config/struct.Config.html#method.max_message_size
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.config = self.config.max_message_size(size);
+        self
+    }
+
+    /// Sets [`Config::max_handshake_size`] for every connection.
+    ///
+    /// [`Config::max_handshake_size`]: config/struct.Config.html#method.max_handshake_size
+    pub fn max_handshake_size(mut self, size: usize) -> Self {
+        self.config = self.config.max_handshake_size(size);
+        self
+    }
+
+    /// Drops clients which haven't completed the TLS and opening handshakes within `timeout`.
+    /// Defaults to 10 seconds. Plain HTTP requests only have to arrive within it, answering them
+    /// isn't cut short.
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.config.handshake_timeout = timeout;
+        self
+    }
+
+    /// Speaks one of `protocols`, in order of preference, with clients offering them in
+    /// `Sec-WebSocket-Protocol`. Clients offering none of them are still accepted, without a
+    /// subprotocol.
+    pub fn protocols(self, protocols: &[&str]) -> Self {
+        let supported: Vec<String> = protocols.iter().map(|x| x.to_string()).collect();
+
+        self.select_protocol(move |offered| supported.iter().find(|x| offered.contains(x)).cloned())
+    }
+
+    /// Lets `select` choose the subprotocol from the ones a client offers, in the client's order
+    /// of preference. Returning `None`, or a protocol the client didn't offer, accepts the
+    /// connection without a subprotocol.
+    pub fn select_protocol<P>(mut self, select: P) -> Self
+    where
+        P: (Fn(&[String]) -> Option<String>) + Send + Sync + 'static,
+    {
+        self.config.protocol = Some(ProtocolSelector(Arc::new(select)));
+        self
+    }
+
+    /// Only accepts browsers connecting from one of `origins`, which are either exact, such as
+    /// `https://example.com`, or allow any subdomain, such as `https://*.example.com`. Other
+    /// origins are refused with 403. Clients which don't send an `Origin`, which browsers always
+    /// do, are accepted.
+    pub fn allowed_origins(self, origins: &[&str]) -> Self {
+        let allowed: Vec<String> = origins.iter().map(|x| x.to_ascii_lowercase()).collect();
+
+        self.check_origin(move |origin| {
+            allowed.iter().any(|x| handshake::origin_matches(x, origin))
+        })
+    }
+
+    /// Lets `allow` decide which origins browsers may connect from, given the `Origin` header in
+    /// lowercase. Refused origins are answered with 403, requests without an `Origin` are
+    /// accepted.
+    pub fn check_origin<P>(mut self, allow: P) -> Self
+    where
+        P: (Fn(&str) -> bool) + Send + Sync + 'static,
+    {
+        self.config.origin = Some(OriginPolicy(Arc::new(allow)));
+        self
+    }
+
+    /// Runs `hook` on every upgrade request before it is answered, see the [`auth`] module.
+    ///
+    /// [`auth`]: auth/index.html
+    pub fn authorize<A: Authorize + 'static>(mut self, hook: A) -> Self {
+        self.config.authorize = Some(Authorizer(Arc::new(hook)));
+        self
+    }
+
+    /// Lets `handler` answer plain HTTP requests, the ones which don't ask for an upgrade, see
+    /// the [`http`] module. Handlers are tried in the order they are added.
+    ///
+    /// [`http`]: http/index.html
+    pub fn http<H: HttpHandler + 'static>(mut self, handler: H) -> Self {
+        self.config.http.push(HttpFallback(Arc::new(handler)));
+        self
+    }
+
+    /// Sets [`Config::deflate`] for every connection.
+    ///
+    /// [`Config::deflate`]: config/struct.Config.html#method.deflate
+    pub fn deflate(mut self, config: DeflateConfig) -> Self {
+        self.config = self.config.deflate(config);
+        self
+    }
+
+    /// Adds a custom extension. `factory` is called for every connection, the instance it
+    /// returns takes part in negotiating the `Sec-WebSocket-Extensions` header and is dropped if
+    /// the client doesn't agree to it.
+    pub fn extension<E, X>(mut self, factory: X) -> Self
+    where
+        E: Extension + 'static,
+        X: (Fn() -> E) + Send + Sync + 'static,
+    {
+        let factory = move || Box::new(factory()) as Box<dyn Extension>;
+        self.config
+            .extensions
+            .push(ExtensionFactory(Arc::new(factory)));
+        self
+    }
+
+    pub async fn listen(&mut self) {
+        loop {
+            let (stream, addr) = match self.sock.accept().await {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+
+            let wrap = self.sock.wrap(stream);
+            let callback = self.callback.clone();
+            let config = self.config.clone();
+
+            // Clients may take their time with the handshakes, or never finish them, which must
+            // not hold up the ones behind them.
+            tokio::spawn(async move {
+                let open = async {
+                    let stream = wrap.await.ok()?;
+                    Connection::accept(stream, &config, Some(addr)).await.ok()
+                };
+
+                let client = match time::timeout(config.handshake_timeout, open).await {
+                    Ok(Some(Opened::Websocket(x))) => x,
+                    // HTTP handlers may take as long as they need.
+                    Ok(Some(Opened::Http(mut stream, request))) => {
+                        Connection::serve_http(&mut stream, &config, request).await;
+                        return;
+                    }
+                    _ => return,
+                };
+
+                let c = (callback)(client.clone());
+                serve(client, c, config).await;
+            });
+        }
+    }
+}
+
+/// Drives a single connection, answering control frames and passing messages on to the handler.
+async fn serve<T, F>(mut client: Connection<T>, mut c: F, config: Config)
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+    F: SocketCallback + Send,
+{
+    c.on_open().await;
+
+    // With a heartbeat configured this is when we either ping the client or, if a ping is already
+    // in flight, give up on it.
+    let mut deadline = config
+        .heartbeat
+        .map(|(interval, _)| Instant::now() + interval);
+    let mut awaiting_pong = false;
+    // Pings go out through a handle of their own while `next` holds on to the connection.
+    let mut pinger = client.clone();
+    // Failing to answer a ping is reported like an error reading from the connection.
+    let mut failed = None;
+
+    // Every way out of the loop ends in exactly one call to `on_close`.
+    let (code, reason) = 'serve: loop {
+        let next = match failed.take() {
+            Some(e) => Some(Err(e)),
+            None => {
+                let next = client.next();
+                pin_mut!(next);
+
+                // The heartbeat only interrupts the wait, not the read itself, which may be half
+                // way through a frame or answering a Close frame.
+                loop {
+                    let (at, timeout) = match (deadline, config.heartbeat) {
+                        (Some(at), Some((_, timeout))) => (at, timeout),
+                        _ => break next.await,
+                    };
+
+                    let tick = time::delay_until(at);
+                    if let Either::Left((x, _)) = future::select(&mut next, tick).await {
+                        break x;
+                    }
+
+                    if awaiting_pong {
+                        pinger.shutdown().await;
+                        break 'serve (
+                            Some(CloseCode::AbnormalClosure),
+                            "heartbeat timed out".into(),
+                        );
+                    }
+
+                    if let Err(e) = pinger.send_raw(Frame::ping()).await {
+                        break Some(Err(e.into()));
+                    }
+
+                    awaiting_pong = true;
+                    deadline = Some(Instant::now() + timeout);
+                }
+            }
+        };
+
+        let frame = match next {
+            Some(Ok(x)) => x,
+            Some(Err(e)) => {
+                c.on_error(&e).await;
+
+                match e {
+                    Error::Protocol(code, reason) => {
+                        let _ = client.close(code, &reason).await;
+                        break (Some(code), reason);
+                    }
+                    Error::Io(_) => {
+                        client.shutdown().await;
+                        break (Some(CloseCode::AbnormalClosure), String::new());
+                    }
+                }
+            }
+            // The client hung up without a closing handshake.
+            None => {
+                client.shutdown().await;
+                break (Some(CloseCode::AbnormalClosure), String::new());
+            }
+        };
+
+        match frame.opcode {
+            Opcode::Close => break (frame.code, frame.get_msg()),
+            Opcode::Ping => {
+                let pong = Frame::pong_with(frame.payload().clone());
+                if let Err(e) = client.send_raw(pong).await {
+                    failed = Some(e.into());
+                }
+            }
+            Opcode::Pong if awaiting_pong => {
+                awaiting_pong = false;
+                deadline = config
+                    .heartbeat
+                    .map(|(interval, _)| Instant::now() + interval);
+            }
+            Opcode::Text | Opcode::Binary => {
+                // `next` has checked text messages to be valid UTF-8 already.
+                let message = unsafe { Message::from_frame_unchecked(&frame) };
+                c.on_message(message).await
+            }
+            _ => {}
+        }
+    };
+
+    c.on_close(code, reason).await;
 }
 
 /// Websocket implementation over TcpStream.
@@ -171,35 +504,21 @@ where
         Self {
             callback: Arc::new(callback),
             sock: Box::new(sock),
+            config: Config::default(),
         }
     }
+}
 
-    pub async fn listen(&mut self) {
-        loop {
-            if let Ok(mut client) = self.sock.accept().await {
-                let callback = self.callback.clone();
-
-                tokio::spawn(async move {
-                    let mut c = (callback)(client.clone());
-                    c.on_open().await;
-
-                    tokio::spawn(async {});
-
-                    loop {
-                        if let Some(Ok(frame)) = client.next().await {
-                            match frame.opcode {
-                                Opcode::Close => {
-                                    c.on_close(frame.reason, frame.message).await;
-                                    break;
-                                }
-                                Opcode::Text => c.on_message(Message::from_frame(&frame)).await,
-                                _ => {}
-                            }
-                        }
-                    }
-                });
-            }
-        }
+impl Websocket<TcpStream, Dispatch<TcpStream>, Handler> {
+    /// Serves every route of `router` on `addr`, see the [`router`] module.
+    ///
+    /// [`router`]: router/index.html
+    pub fn with_router(addr: &str, router: Router<TcpStream>) -> Self {
+        let routes = router.routes();
+        let mut server = Self::build(addr, router.into_dispatch());
+
+        server.config.routes = Some(routes);
+        server
     }
 }
 
@@ -228,32 +547,20 @@ where
         Self {
             callback: Arc::new(callback),
             sock: Box::new(sock),
+            config: Config::default(),
         }
     }
+}
 
-    pub async fn listen(&mut self) {
-        loop {
-            if let Ok(mut client) = self.sock.accept().await {
-                let callback = self.callback.clone();
-
-                tokio::spawn(async move {
-                    let mut c = (callback)(client.clone());
-                    c.on_open().await;
-
-                    loop {
-                        if let Some(Ok(frame)) = client.next().await {
-                            match frame.opcode {
-                                Opcode::Close => {
-                                    c.on_close(frame.reason, frame.message).await;
-                                    break;
-                                }
-                                Opcode::Text => c.on_message(Message::from_frame(&frame)).await,
-                                _ => {}
-                            }
-                        }
-                    }
-                });
-            }
-        }
+impl Websocket<SslStream, Dispatch<SslStream>, Handler> {
+    /// Serves every route of `router` on `addr` over SSL, see the [`router`] module.
+    ///
+    /// [`router`]: router/index.html
+    pub fn with_router(addr: &str, router: Router<SslStream>, cert: &str) -> Self {
+        let routes = router.routes();
+        let mut server = Self::build(addr, router.into_dispatch(), cert);
+
+        server.config.routes = Some(routes);
+        server
     }
 }
@@ -10,7 +10,7 @@ impl SocketCallback for Test {
         println!("On open");
     }
 
-    async fn on_close(&mut self, close_code: Option<u32>, reason: String) {
+    async fn on_close(&mut self, close_code: Option<CloseCode>, reason: String) {
         println!("Closed with reason: {} and code: {:?}", reason, close_code);
     }
 
@@ -1,31 +1,182 @@
-use crate::frame::Frame;
+use crate::frame::{Frame, Opcode};
+use bytes::Bytes;
+use std::{convert::TryFrom, fmt, ops::Deref, str::Utf8Error};
 
-pub struct Message {
-    data: String,
+/// A complete message sent or received over a websocket connection.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(Utf8Bytes),
+    Binary(Bytes),
 }
 
 impl Message {
     pub fn new(data: String) -> Self {
-        Self { data }
+        Self::Text(data.into())
     }
 
     pub fn from<T: Into<String>>(a: T) -> Self {
-        Self { data: a.into() }
+        Self::Text(a.into().into())
     }
 
+    pub fn binary<T: Into<Bytes>>(a: T) -> Self {
+        Self::Binary(a.into())
+    }
+
+    /// Creates a message sharing the payload of `a`. Text that isn't valid UTF-8 is replaced
+    /// lossily, text frames returned by [`Connection::next`] have already been validated.
+    ///
+    /// [`Connection::next`]: ../connection/struct.Connection.html#method.next
     pub fn from_frame(a: &Frame) -> Self {
-        Self { data: a.get_msg() }
+        match a.opcode {
+            Opcode::Binary => Self::Binary(a.payload().clone()),
+            _ => Self::Text(
+                Utf8Bytes::try_from(a.payload().clone()).unwrap_or_else(|_| a.get_msg().into()),
+            ),
+        }
+    }
+
+    /// Like [`Message::from_frame`], without checking the payload of a text frame again.
+    ///
+    /// # Safety
+    ///
+    /// Unless `a` is a Binary frame its payload must be valid UTF-8, as it is for the frames
+    /// returned by [`Connection::next`].
+    ///
+    /// [`Message::from_frame`]: enum.Message.html#method.from_frame
+    /// [`Connection::next`]: ../connection/struct.Connection.html#method.next
+    pub(crate) unsafe fn from_frame_unchecked(a: &Frame) -> Self {
+        match a.opcode {
+            Opcode::Binary => Self::Binary(a.payload().clone()),
+            _ => Self::Text(Utf8Bytes::from_bytes_unchecked(a.payload().clone())),
+        }
+    }
+
+    pub fn is_text(&self) -> bool {
+        matches!(self, Self::Text(_))
+    }
+
+    pub fn is_binary(&self) -> bool {
+        matches!(self, Self::Binary(_))
+    }
+
+    /// Borrows the text of a text message.
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            Self::Text(x) => Some(x),
+            _ => None,
+        }
+    }
+
+    /// Returns the raw payload of the message, for text messages these are the UTF-8 bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::Text(x) => x.as_bytes(),
+            Self::Binary(x) => x.as_ref(),
+        }
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        match self {
+            Self::Text(x) => x.into_bytes(),
+            Self::Binary(x) => x,
+        }
     }
 }
 
 impl Into<Frame> for Message {
     fn into(self) -> Frame {
-        Frame::new(self.data)
+        match self {
+            Self::Text(x) => Frame::new(x.into_bytes()),
+            Self::Binary(x) => Frame::binary(x),
+        }
     }
 }
 
+/// Binary messages are converted lossily, use [`Message::as_bytes`] to get at the raw payload.
+///
+/// [`Message::as_bytes`]: enum.Message.html#method.as_bytes
 impl ToString for Message {
     fn to_string(&self) -> String {
-        self.data.clone()
+        match self {
+            Self::Text(x) => x.to_string(),
+            Self::Binary(x) => String::from_utf8_lossy(x).into_owned(),
+        }
+    }
+}
+
+/// A `Bytes` buffer known to hold valid UTF-8, it derefs to `str` without copying.
+#[derive(Clone, Default, PartialEq, Eq, Hash)]
+pub struct Utf8Bytes(Bytes);
+
+impl Utf8Bytes {
+    pub fn as_str(&self) -> &str {
+        // The contents were checked to be UTF-8 when the value was created.
+        unsafe { std::str::from_utf8_unchecked(&self.0) }
+    }
+
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+
+    /// Wraps `bytes` without checking them.
+    ///
+    /// # Safety
+    ///
+    /// `bytes` must be valid UTF-8.
+    pub(crate) unsafe fn from_bytes_unchecked(bytes: Bytes) -> Self {
+        Self(bytes)
+    }
+}
+
+impl Deref for Utf8Bytes {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl TryFrom<Bytes> for Utf8Bytes {
+    type Error = Utf8Error;
+
+    fn try_from(bytes: Bytes) -> Result<Self, Self::Error> {
+        std::str::from_utf8(&bytes)?;
+        Ok(Self(bytes))
+    }
+}
+
+impl From<String> for Utf8Bytes {
+    fn from(s: String) -> Self {
+        Self(s.into())
+    }
+}
+
+impl From<&'static str> for Utf8Bytes {
+    fn from(s: &'static str) -> Self {
+        Self(s.into())
+    }
+}
+
+impl PartialEq<str> for Utf8Bytes {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Utf8Bytes {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl fmt::Debug for Utf8Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Display for Utf8Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
     }
 }
@@ -0,0 +1,188 @@
+//! Dispatching connections to different handlers depending on the path they were opened on.
+//!
+//! ```rust no_run
+//! use quicksockets::{prelude::*, router::Router, Websocket};
+//!
+//! struct Chat {
+//!     conn: Connection<TcpStream>,
+//! }
+//!
+//! #[async_trait]
+//! impl SocketCallback for Chat {
+//!     async fn on_open(&mut self) {
+//!         let room = self.conn.param("room").unwrap_or_default().to_string();
+//!         self.conn.send(Message::new(room)).await.unwrap();
+//!     }
+//!
+//!     async fn on_close(&mut self, _: Option<CloseCode>, _: String) {}
+//! }
+//!
+//! #[tokio::main]
+//! async fn main() {
+//!     let router = Router::new().route("/chat/:room", |x| Chat { conn: x });
+//!
+//!     Websocket::<TcpStream, _, _>::with_router("127.0.0.1:4545", router)
+//!         .listen()
+//!         .await;
+//! }
+//! ```
+use crate::{connection::Connection, SocketCallback};
+use std::collections::HashMap;
+use tokio::prelude::{AsyncRead, AsyncWrite};
+
+/// A handler created by one of the routes of a [`Router`].
+///
+/// [`Router`]: struct.Router.html
+pub type Handler = Box<dyn SocketCallback + Send + Sync>;
+
+/// Hands each connection to the handler of the route it was opened on.
+pub type Dispatch<T> = Box<dyn (Fn(Connection<T>) -> Handler) + Send + Sync>;
+
+type Factory<T> = Box<dyn (Fn(Connection<T>) -> Handler) + Send + Sync>;
+
+/// A path pattern made of `/` separated segments. A segment starting with `:` matches any single
+/// segment and captures it under the name following the colon, a last segment starting with `*`
+/// matches the rest of the path, captured under the name following the star if there is one.
+///
+/// Empty segments are ignored, so `/chat/` and `/chat` are the same path. Captured values are
+/// taken from the path as sent, without percent-decoding.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    pattern: String,
+    segments: Vec<Segment>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Static(String),
+    Param(String),
+    Wildcard(String),
+}
+
+impl Route {
+    /// Parses `pattern`, panicking if a wildcard isn't its last segment.
+    pub fn new(pattern: &str) -> Self {
+        let segments: Vec<Segment> = split(pattern)
+            .map(|x| {
+                if let Some(name) = x.strip_prefix(':') {
+                    Segment::Param(name.to_string())
+                } else if let Some(name) = x.strip_prefix('*') {
+                    Segment::Wildcard(name.to_string())
+                } else {
+                    Segment::Static(x.to_string())
+                }
+            })
+            .collect();
+
+        let wildcard = segments
+            .iter()
+            .position(|x| matches!(x, Segment::Wildcard(_)));
+
+        if let Some(i) = wildcard {
+            assert!(
+                i == segments.len() - 1,
+                "wildcards must be the last segment of a route: {}",
+                pattern
+            );
+        }
+
+        Self {
+            pattern: pattern.to_string(),
+            segments,
+        }
+    }
+
+    /// The pattern this route was created from.
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Matches `path`, without its query string, against the route and returns the captured
+    /// parameters if it does.
+    pub fn matches(&self, path: &str) -> Option<HashMap<String, String>> {
+        let mut params = HashMap::new();
+        let mut parts = split(path);
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Static(x) => {
+                    if parts.next() != Some(x.as_str()) {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    params.insert(name.clone(), parts.next()?.to_string());
+                }
+                Segment::Wildcard(name) => {
+                    let rest: Vec<&str> = parts.by_ref().collect();
+
+                    if !name.is_empty() {
+                        params.insert(name.clone(), rest.join("/"));
+                    }
+                }
+            }
+        }
+
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(params)
+    }
+}
+
+fn split(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|x| !x.is_empty())
+}
+
+/// Routes registered in order, the first one matching the path of a connection gets it. The
+/// opening handshake of connections matching none of them is answered with 404.
+pub struct Router<T: AsyncRead + AsyncWrite> {
+    routes: Vec<(Route, Factory<T>)>,
+}
+
+impl<T> Router<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    pub fn new() -> Self {
+        Self { routes: Vec::new() }
+    }
+
+    /// Hands connections opened on a path matching `pattern` to the handler `factory` creates.
+    pub fn route<R, F>(mut self, pattern: &str, factory: R) -> Self
+    where
+        R: (Fn(Connection<T>) -> F) + Send + Sync + 'static,
+        F: SocketCallback + Send + Sync + 'static,
+    {
+        let factory = move |x| Box::new(factory(x)) as Handler;
+        self.routes.push((Route::new(pattern), Box::new(factory)));
+        self
+    }
+
+    /// The routes the handshake checks the request target against.
+    pub(crate) fn routes(&self) -> Vec<Route> {
+        self.routes.iter().map(|(route, _)| route.clone()).collect()
+    }
+
+    pub(crate) fn into_dispatch(self) -> Dispatch<T> {
+        Box::new(move |conn| {
+            let (_, factory) = self
+                .routes
+                .iter()
+                .find(|(route, _)| route.matches(conn.route()).is_some())
+                .expect("the handshake only accepts connections to known routes");
+
+            factory(conn)
+        })
+    }
+}
+
+impl<T> Default for Router<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,15 +1,29 @@
-use crate::connection::Connection;
 use async_trait::async_trait;
-use tokio::io::{AsyncRead, AsyncWrite};
+use futures::future::BoxFuture;
+use std::net::SocketAddr;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
 
 pub mod ssl;
 pub mod tcp;
 
+/// Sets up the stream websocket traffic goes over on top of a freshly accepted TCP connection.
+pub type Wrap<T> = BoxFuture<'static, Result<T, Box<dyn std::error::Error + Send + Sync>>>;
+
 #[async_trait]
 pub trait Stream: Send + Sync
 where
     Self::Out: AsyncWrite + AsyncRead,
 {
     type Out;
-    async fn accept(&mut self) -> Result<Connection<Self::Out>, Box<dyn std::error::Error>>;
+
+    /// Waits for the next TCP connection. Nothing slow may happen here, whatever a client has to
+    /// go through before it is connected belongs in [`wrap`], which runs on a task of its own.
+    ///
+    /// [`wrap`]: #tymethod.wrap
+    async fn accept(&mut self) -> Result<(TcpStream, SocketAddr), std::io::Error>;
+
+    fn wrap(&self, stream: TcpStream) -> Wrap<Self::Out>;
 }
@@ -1,7 +1,8 @@
-use crate::connection::Connection;
-use crate::streams::Stream;
+use crate::streams::{Stream, Wrap};
 use async_trait::async_trait;
-use tokio::net::{TcpListener, ToSocketAddrs};
+use futures::future::FutureExt;
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
 use tokio_tls::{TlsAcceptor, TlsStream};
 
 pub struct Ssl {
@@ -22,12 +23,15 @@ impl Ssl {
 
 #[async_trait]
 impl Stream for Ssl {
-    type Out = TlsStream<tokio::net::TcpStream>;
+    type Out = TlsStream<TcpStream>;
 
-    async fn accept(&mut self) -> Result<Connection<Self::Out>, Box<dyn std::error::Error>> {
-        let (stream, _) = self.sock.accept().await?;
+    async fn accept(&mut self) -> Result<(TcpStream, SocketAddr), std::io::Error> {
+        self.sock.accept().await
+    }
+
+    fn wrap(&self, stream: TcpStream) -> Wrap<Self::Out> {
         let acceptor = self.acceptor.clone();
 
-        Ok(Connection::new(acceptor.accept(stream).await?).await?)
+        async move { Ok(acceptor.accept(stream).await?) }.boxed()
     }
 }
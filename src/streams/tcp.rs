@@ -1,7 +1,8 @@
-use crate::connection::Connection;
-use crate::streams::Stream;
+use crate::streams::{Stream, Wrap};
 use async_trait::async_trait;
-use tokio::net::{TcpListener, ToSocketAddrs};
+use futures::future::FutureExt;
+use std::net::SocketAddr;
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
 
 pub struct Tcp {
     sock: TcpListener,
@@ -17,11 +18,13 @@ impl Tcp {
 
 #[async_trait]
 impl Stream for Tcp {
-    type Out = tokio::net::TcpStream;
+    type Out = TcpStream;
 
-    async fn accept(&mut self) -> Result<Connection<Self::Out>, Box<dyn std::error::Error>> {
-        let (stream, _) = self.sock.accept().await?;
+    async fn accept(&mut self) -> Result<(TcpStream, SocketAddr), std::io::Error> {
+        self.sock.accept().await
+    }
 
-        Ok(Connection::new(stream).await?)
+    fn wrap(&self, stream: TcpStream) -> Wrap<Self::Out> {
+        async move { Ok(stream) }.boxed()
     }
 }
@@ -0,0 +1,135 @@
+//! The authorization hook accepting, decorating and refusing upgrades.
+mod common;
+
+use common::Server;
+use quicksockets::{
+    auth::{Accept, Authorize},
+    prelude::*,
+    Request, Response, Websocket,
+};
+use std::time::Duration;
+use tokio::{io::AsyncWriteExt, time};
+
+static SERVER: Server = Server::new("127.0.0.1:9005", |addr| {
+    common::serve(
+        Websocket::<TcpStream, _, _>::build(addr, |x| Greet { conn: x }).authorize(Tokens),
+    )
+});
+
+struct User(String);
+
+/// Lets in clients with the right bearer token or session cookie.
+struct Tokens;
+
+#[async_trait]
+impl Authorize for Tokens {
+    async fn authorize(&self, request: &Request) -> Result<Accept, Response> {
+        // Checking a token usually means asking something else.
+        time::delay_for(Duration::from_millis(5)).await;
+
+        if request.cookie("session") == Some("ok") {
+            return Ok(Accept::new().identity(User("cookie".into())));
+        }
+
+        match request.header("authorization") {
+            Some("Bearer good") => Ok(Accept::new()
+                .header("Set-Cookie", "session=ok")
+                .identity(User("token".into()))),
+            Some("Bearer slow") => {
+                time::delay_for(Duration::from_secs(5)).await;
+                Ok(Accept::new())
+            }
+            Some("Bearer teapot") => Err(Response::new(418).body("short and stout")),
+            _ => Err(Response::new(401)
+                .header("WWW-Authenticate", "Bearer")
+                .body("who are you?")),
+        }
+    }
+}
+
+/// Greets clients with who the hook said they are.
+struct Greet {
+    conn: Connection<TcpStream>,
+}
+
+#[async_trait]
+impl SocketCallback for Greet {
+    async fn on_open(&mut self) {
+        let user = self.conn.identity::<User>().map(|x| x.0.clone());
+        let user = user.unwrap_or_else(|| "nobody".into());
+        let _ = self.conn.send(Message::new(user)).await;
+    }
+
+    async fn on_close(&mut self, _: Option<CloseCode>, _: String) {}
+}
+
+/// Sends an upgrade request with `extra` headers and returns everything the server sends until
+/// it goes quiet.
+async fn upgrade(extra: &str) -> String {
+    let mut stream = SERVER.connect().await;
+    let req = common::request("/", extra);
+    stream.write_all(req.as_bytes()).await.unwrap();
+
+    String::from_utf8_lossy(&common::read_all(&mut stream).await).into_owned()
+}
+
+#[tokio::test]
+async fn accepted_with_token() {
+    let response = upgrade("Authorization: Bearer good\r\n").await;
+
+    assert!(response.starts_with("HTTP/1.1 101"));
+    assert!(response.contains("\r\nSet-Cookie: session=ok\r\n"));
+    assert!(response.ends_with("\x05token"));
+}
+
+#[tokio::test]
+async fn accepted_with_cookie() {
+    let response = upgrade("Cookie: session=ok\r\n").await;
+
+    assert!(response.starts_with("HTTP/1.1 101"));
+    assert!(response.ends_with("\x06cookie"));
+}
+
+#[tokio::test]
+async fn rejected() {
+    let response = upgrade("").await;
+
+    assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"));
+    assert!(response.contains("\r\nWWW-Authenticate: Bearer\r\n"));
+    assert!(response.contains("\r\nConnection: close\r\n"));
+    assert!(response.ends_with("\r\n\r\nwho are you?"));
+}
+
+#[tokio::test]
+async fn rejected_with_any_status() {
+    let response = upgrade("Authorization: Bearer teapot\r\n").await;
+
+    assert!(response.starts_with("HTTP/1.1 418 \r\n"));
+    assert!(response.ends_with("short and stout"));
+}
+
+#[tokio::test]
+async fn slow_hook_does_not_hold_up_others() {
+    let slow = tokio::spawn(upgrade("Authorization: Bearer slow\r\n"));
+    time::delay_for(Duration::from_millis(50)).await;
+
+    let other = time::timeout(Duration::from_secs(2), upgrade("Cookie: session=ok\r\n")).await;
+    assert!(other
+        .expect("a slow hook held up other clients")
+        .starts_with("HTTP/1.1 101"));
+
+    drop(slow);
+}
+
+#[test]
+fn response_bytes() {
+    let response = Response::new(403)
+        .header("X-Reason", "banned")
+        .header("X-Bad", "a\r\nSet-Cookie: x=y")
+        .body("no");
+
+    assert_eq!(
+        response.to_bytes(),
+        b"HTTP/1.1 403 Forbidden\r\nX-Reason: banned\r\nContent-Length: 2\r\n\r\nno".to_vec()
+    );
+}
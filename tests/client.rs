@@ -0,0 +1,63 @@
+//! The client end of a connection, opened from a `ws://` URL.
+mod common;
+
+use common::{Echo, Server};
+use quicksockets::{prelude::*, Websocket};
+
+static SERVER: Server = Server::new("127.0.0.1:9014", |addr| {
+    common::serve(Websocket::<TcpStream, _, _>::build(addr, Echo::new))
+});
+
+/// Returns why connecting to `url` failed.
+async fn error(url: &str) -> String {
+    match Connection::<TcpStream>::connect(url).await {
+        Ok(_) => panic!("connected to {}", url),
+        Err(e) => e.to_string(),
+    }
+}
+
+#[tokio::test]
+async fn round_trip() {
+    SERVER.connect().await;
+
+    let url = format!("ws://{}/echo?x=1#ignored", SERVER.addr());
+    let mut conn = Connection::<TcpStream>::connect(&url).await.unwrap();
+    let big = "a".repeat(70000);
+
+    conn.send(Message::new("hello".into())).await.unwrap();
+    conn.send(Message::new(big.clone())).await.unwrap();
+
+    assert_eq!(conn.next().await.unwrap().unwrap().get_msg(), "hello");
+    assert_eq!(conn.next().await.unwrap().unwrap().get_msg(), big);
+    conn.close(CloseCode::NormalClosure, "bye").await.unwrap();
+}
+
+#[tokio::test]
+async fn invalid_urls() {
+    assert!(error("http://localhost/")
+        .await
+        .contains("not a ws:// or wss:// URL"));
+    assert!(error("ws:///path").await.contains("has no host"));
+    assert!(error("ws://localhost:port/").await.contains("invalid port"));
+    assert!(error("ws://localhost:99999/")
+        .await
+        .contains("invalid port"));
+}
+
+#[tokio::test]
+async fn wrong_stream_for_the_scheme() {
+    assert!(error("wss://localhost/")
+        .await
+        .contains("needs a Connection<SslStream>"));
+}
+
+#[tokio::test]
+async fn ipv6_authority() {
+    assert!(error("ws://[::1/")
+        .await
+        .contains("unterminated IPv6 address"));
+    assert!(error("ws://[::1]x80/").await.contains("invalid port"));
+    assert!(error("ws://[::1]80/").await.contains("invalid port"));
+    assert!(error("ws://[::1]:/").await.contains("invalid port"));
+    assert!(error("ws://[]/").await.contains("has no host"));
+}
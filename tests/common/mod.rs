@@ -0,0 +1,231 @@
+//! Pieces the integration tests share: an echo handler, a server started once per test binary and
+//! clients talking to it over loopback.
+#![allow(dead_code)]
+
+use futures::future::{FutureExt, LocalBoxFuture};
+use quicksockets::{prelude::*, Websocket};
+use std::{sync::Once, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time,
+};
+
+/// The masking key of every frame the tests send.
+pub const KEY: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+/// How long the server gets to answer before a test fails.
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Sends every message back to the client it came from.
+pub struct Echo {
+    pub conn: Connection<TcpStream>,
+}
+
+impl Echo {
+    pub fn new(conn: Connection<TcpStream>) -> Self {
+        Self { conn }
+    }
+}
+
+#[async_trait]
+impl SocketCallback for Echo {
+    async fn on_close(&mut self, _: Option<CloseCode>, _: String) {}
+
+    async fn on_message(&mut self, message: Message) {
+        let _ = self.conn.send(message).await;
+    }
+}
+
+/// Runs `server`, for the function a [`Server`] is started with.
+pub fn serve<T, R, F>(mut server: Websocket<T, R, F>) -> LocalBoxFuture<'static, ()>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    R: (Fn(Connection<T>) -> F) + Send + Sync + 'static,
+    F: SocketCallback + Send + Sync + 'static,
+{
+    async move { server.listen().await }.boxed_local()
+}
+
+/// A server on `addr`, started by `serve` on a runtime of its own the first time a test connects
+/// to it, so it outlives the runtime of any single test.
+pub struct Server {
+    addr: &'static str,
+    serve: fn(&'static str) -> LocalBoxFuture<'static, ()>,
+    start: Once,
+}
+
+impl Server {
+    pub const fn new(
+        addr: &'static str,
+        serve: fn(&'static str) -> LocalBoxFuture<'static, ()>,
+    ) -> Self {
+        Self {
+            addr,
+            serve,
+            start: Once::new(),
+        }
+    }
+
+    pub fn addr(&self) -> &'static str {
+        self.addr
+    }
+
+    /// Opens a TCP connection to the server, starting it unless it is running already and giving
+    /// it a second to come up.
+    pub async fn connect(&self) -> TcpStream {
+        let (addr, serve) = (self.addr, self.serve);
+
+        self.start.call_once(|| {
+            std::thread::spawn(move || {
+                let mut rt = tokio::runtime::Runtime::new().unwrap();
+                rt.block_on(async { serve(addr).await });
+            });
+        });
+
+        for _ in 0..50 {
+            if let Ok(x) = TcpStream::connect(self.addr).await {
+                return x;
+            }
+
+            time::delay_for(Duration::from_millis(20)).await;
+        }
+
+        panic!("the server didn't start");
+    }
+
+    /// Opens a connection upgraded for `target`, failing the test unless the server agrees.
+    pub async fn open(&self, target: &str) -> TcpStream {
+        let mut stream = self.connect().await;
+        let response = upgrade(&mut stream, target, "").await;
+
+        assert!(response.starts_with("HTTP/1.1 101"), "{}", response);
+        stream
+    }
+
+    /// Opens a connection offering `extensions` and returns it along with the extensions the
+    /// server agreed to, if any.
+    pub async fn negotiate(&self, extensions: &str) -> (TcpStream, Option<String>) {
+        let mut stream = self.connect().await;
+        let extra = format!("Sec-WebSocket-Extensions: {}\r\n", extensions);
+        let response = upgrade(&mut stream, "/", &extra).await;
+        assert!(response.starts_with("HTTP/1.1 101"), "{}", response);
+
+        let accepted = response
+            .lines()
+            .find_map(|x| x.strip_prefix("Sec-WebSocket-Extensions: "))
+            .map(String::from);
+
+        (stream, accepted)
+    }
+
+    /// Sends `req` over a connection of its own and returns the status code of the response, 0 if
+    /// there is none.
+    pub async fn status(&self, req: impl AsRef<[u8]>) -> u16 {
+        let mut stream = self.connect().await;
+        send(&mut stream, req.as_ref()).await;
+
+        let mut response = Vec::new();
+        let mut buf = [0; 1024];
+
+        while !response.windows(2).any(|x| x == b"\r\n") {
+            match time::timeout(TIMEOUT, stream.read(&mut buf)).await {
+                Ok(Ok(n)) if n > 0 => response.extend_from_slice(&buf[..n]),
+                _ => break,
+            }
+        }
+
+        String::from_utf8_lossy(&response)
+            .split(' ')
+            .nth(1)
+            .and_then(|x| x.parse().ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Writes `data` to the server, which may hang up before it has read all of it.
+pub async fn send(stream: &mut TcpStream, data: &[u8]) {
+    let _ = stream.write_all(data).await;
+}
+
+/// Builds an upgrade request for `target` with `extra` headers added before the empty line.
+pub fn request(target: &str, extra: &str) -> String {
+    format!(
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n{}\r\n",
+        target, extra
+    )
+}
+
+/// Sends an upgrade request for `target` and returns the response head. It is read a byte at a
+/// time so none of the frames behind it are swallowed.
+pub async fn upgrade(stream: &mut TcpStream, target: &str, extra: &str) -> String {
+    let req = request(target, extra);
+    stream.write_all(req.as_bytes()).await.unwrap();
+
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        response.push(stream.read_u8().await.unwrap());
+    }
+
+    String::from_utf8(response).unwrap()
+}
+
+/// A masked client frame. `first` is the first byte of the header: FIN, RSV and opcode.
+pub fn frame(first: u8, payload: &[u8]) -> Vec<u8> {
+    let mut out = vec![first];
+
+    match payload.len() {
+        len if len < 126 => out.push(0x80 | len as u8),
+        len if len <= 65535 => {
+            out.push(0x80 | 126);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            out.push(0x80 | 127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+
+    out.extend_from_slice(&KEY);
+    out.extend(payload.iter().enumerate().map(|(i, b)| b ^ KEY[i % 4]));
+    out
+}
+
+/// Reads the next frame from the server, `None` once it hangs up.
+pub async fn read_frame(stream: &mut TcpStream) -> Option<(u8, Vec<u8>)> {
+    let read = async {
+        let mut header = [0; 2];
+        stream.read_exact(&mut header).await.ok()?;
+        assert_eq!(header[1] & 0x80, 0, "server frames must not be masked");
+
+        let len = match header[1] & 0x7f {
+            126 => stream.read_u16().await.ok()? as usize,
+            127 => stream.read_u64().await.ok()? as usize,
+            len => len as usize,
+        };
+
+        let mut payload = vec![0; len];
+        stream.read_exact(&mut payload).await.ok()?;
+
+        Some((header[0], payload))
+    };
+
+    time::timeout(TIMEOUT, read)
+        .await
+        .expect("the server didn't answer")
+}
+
+/// Reads everything the server sends until it goes quiet or hangs up.
+pub async fn read_all(stream: &mut TcpStream) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut buf = [0; 4096];
+
+    while let Ok(Ok(n)) = time::timeout(Duration::from_millis(300), stream.read(&mut buf)).await {
+        if n == 0 {
+            break;
+        }
+
+        out.extend_from_slice(&buf[..n]);
+    }
+
+    out
+}
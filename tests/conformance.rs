@@ -0,0 +1,359 @@
+//! RFC 6455 conformance cases, modelled on the Autobahn test suite.
+//!
+//! Every case connects to an echo server over loopback, writes raw bytes and checks what comes
+//! back, so each case shows up as its own pass or fail in the test report. The numbers in the case
+//! names follow the Autobahn categories:
+//!
+//! 1. Framing
+//! 2. Pings and pongs
+//! 3. Reserved bits
+//! 4. Opcodes
+//! 5. Fragmentation
+//! 6. UTF-8 handling
+//! 7. Close handling
+//!
+//! Run them with `cargo test --test conformance`.
+mod common;
+
+use common::{frame, read_frame, Echo, Server};
+use quicksockets::{prelude::*, Websocket};
+use std::time::Duration;
+use tokio::{io::AsyncWriteExt, time};
+
+static SERVER: Server = Server::new("127.0.0.1:9001", |addr| {
+    common::serve(Websocket::<TcpStream, _, _>::build(addr, Echo::new))
+});
+
+/// Bytes the client writes, each chunk with a separate write.
+struct Chunks(Vec<Vec<u8>>);
+
+impl From<Vec<u8>> for Chunks {
+    fn from(data: Vec<u8>) -> Self {
+        Self(vec![data])
+    }
+}
+
+/// Writes `data` a few bytes at a time, leaving the server with partial frames to deal with.
+fn chopped(data: Vec<u8>, size: usize) -> Chunks {
+    Chunks(data.chunks(size).map(<[u8]>::to_vec).collect())
+}
+
+/// What the server is expected to do next.
+#[derive(Debug)]
+enum Expect {
+    /// Send a frame with this first byte and payload.
+    Frame(u8, Vec<u8>),
+    /// Send a Close frame with this status code, if any.
+    Close(Option<u16>),
+    /// Hang up.
+    Eof,
+}
+
+fn text(payload: &[u8]) -> Vec<u8> {
+    frame(0x81, payload)
+}
+
+fn binary(payload: &[u8]) -> Vec<u8> {
+    frame(0x82, payload)
+}
+
+fn ping(payload: &[u8]) -> Vec<u8> {
+    frame(0x89, payload)
+}
+
+fn pong(payload: &[u8]) -> Vec<u8> {
+    frame(0x8a, payload)
+}
+
+fn close(code: u16, reason: &[u8]) -> Vec<u8> {
+    let mut payload = code.to_be_bytes().to_vec();
+    payload.extend_from_slice(reason);
+    frame(0x88, &payload)
+}
+
+fn echo(first: u8, payload: &[u8]) -> Expect {
+    Expect::Frame(first, payload.to_vec())
+}
+
+fn closed(code: u16) -> Expect {
+    Expect::Close(Some(code))
+}
+
+fn repeat(len: usize) -> Vec<u8> {
+    b"*".repeat(len)
+}
+
+async fn run(send: Vec<Chunks>, expect: Vec<Expect>) {
+    let mut stream = SERVER.open("/").await;
+
+    for chunk in send.into_iter().flat_map(|x| x.0) {
+        // The server may hang up half way through a case that breaks the protocol.
+        if stream.write_all(&chunk).await.is_err() {
+            break;
+        }
+
+        time::delay_for(Duration::from_millis(1)).await;
+    }
+
+    for expected in expect {
+        let frame = read_frame(&mut stream).await;
+
+        match (&expected, frame) {
+            (Expect::Frame(first, payload), Some((x, data))) if x == *first => {
+                assert!(
+                    data == *payload,
+                    "expected {:?}, got a different payload",
+                    expected
+                )
+            }
+            (Expect::Close(code), Some((0x88, data))) => {
+                let got = match data.len() {
+                    0 => None,
+                    _ => Some(u16::from_be_bytes([data[0], data[1]])),
+                };
+
+                assert_eq!(*code, got, "wrong close code");
+            }
+            (Expect::Eof, None) => {}
+            (_, Some((first, data))) => panic!(
+                "expected {:?}, got a frame starting with {:#x} and {} bytes of payload",
+                expected,
+                first,
+                data.len()
+            ),
+            (_, None) => panic!("expected {:?}, the server hung up", expected),
+        }
+    }
+}
+
+macro_rules! cases {
+    ($($(#[$meta:meta])* $name:ident: [$($send:expr),* $(,)?] => [$($expect:expr),* $(,)?];)*) => {
+        $(
+            $(#[$meta])*
+            #[tokio::test]
+            async fn $name() {
+                run(vec![$(Chunks::from($send)),*], vec![$($expect),*]).await;
+            }
+        )*
+    };
+}
+
+// 1. Framing
+cases! {
+    case_1_1_1_text_empty: [text(b"")] => [echo(0x81, b"")];
+    case_1_1_2_text_125: [text(&repeat(125))] => [echo(0x81, &repeat(125))];
+    case_1_1_3_text_126: [text(&repeat(126))] => [echo(0x81, &repeat(126))];
+    case_1_1_4_text_127: [text(&repeat(127))] => [echo(0x81, &repeat(127))];
+    case_1_1_5_text_128: [text(&repeat(128))] => [echo(0x81, &repeat(128))];
+    case_1_1_6_text_65535: [text(&repeat(65535))] => [echo(0x81, &repeat(65535))];
+    case_1_1_7_text_65536: [text(&repeat(65536))] => [echo(0x81, &repeat(65536))];
+    case_1_1_8_text_65536_chopped: [chopped(text(&repeat(65536)), 997)] => [
+        echo(0x81, &repeat(65536)),
+    ];
+    case_1_2_1_binary_empty: [binary(b"")] => [echo(0x82, b"")];
+    case_1_2_2_binary_125: [binary(&[0xfe; 125])] => [echo(0x82, &[0xfe; 125])];
+    case_1_2_3_binary_126: [binary(&[0xfe; 126])] => [echo(0x82, &[0xfe; 126])];
+    case_1_2_6_binary_65535: [binary(&[0xfe; 65535])] => [echo(0x82, &[0xfe; 65535])];
+    case_1_2_7_binary_65536: [binary(&[0xfe; 65536])] => [echo(0x82, &[0xfe; 65536])];
+    case_1_2_8_binary_65536_chopped: [chopped(binary(&[0xfe; 65536]), 997)] => [
+        echo(0x82, &[0xfe; 65536]),
+    ];
+    case_1_3_1_header_chopped: [chopped(text(b"Hello"), 1)] => [echo(0x81, b"Hello")];
+    case_1_3_2_frames_in_one_write: [[text(b"one"), text(b"two")].concat()] => [
+        echo(0x81, b"one"),
+        echo(0x81, b"two"),
+    ];
+}
+
+// 2. Pings and pongs
+cases! {
+    case_2_1_ping_empty: [ping(b"")] => [echo(0x8a, b"")];
+    case_2_2_ping_text: [ping(b"Hello, world!")] => [echo(0x8a, b"Hello, world!")];
+    case_2_3_ping_binary: [ping(&[0x00, 0xff, 0xfe, 0xfd, 0xfc])] => [
+        echo(0x8a, &[0x00, 0xff, 0xfe, 0xfd, 0xfc]),
+    ];
+    case_2_4_ping_125: [ping(&repeat(125))] => [echo(0x8a, &repeat(125))];
+    case_2_5_ping_126: [ping(&repeat(126))] => [closed(1002)];
+    case_2_6_ping_chopped: [chopped(ping(&repeat(125)), 1)] => [echo(0x8a, &repeat(125))];
+    case_2_7_unsolicited_pong_empty: [pong(b""), text(b"after")] => [echo(0x81, b"after")];
+    case_2_8_unsolicited_pong: [pong(b"unsolicited"), text(b"after")] => [echo(0x81, b"after")];
+    case_2_9_pong_then_ping: [pong(b"unsolicited"), ping(b"ping")] => [echo(0x8a, b"ping")];
+    case_2_10_ten_pings: [ping(b"ping").repeat(10)] => [
+        echo(0x8a, b"ping"), echo(0x8a, b"ping"), echo(0x8a, b"ping"), echo(0x8a, b"ping"),
+        echo(0x8a, b"ping"), echo(0x8a, b"ping"), echo(0x8a, b"ping"), echo(0x8a, b"ping"),
+        echo(0x8a, b"ping"), echo(0x8a, b"ping"),
+    ];
+}
+
+// 3. Reserved bits
+cases! {
+    case_3_1_rsv1_text: [frame(0xc1, b"Hello")] => [closed(1002)];
+    case_3_2_rsv2_after_valid_text: [text(b"Hello"), frame(0xa1, b"Hello")] => [
+        echo(0x81, b"Hello"),
+        closed(1002),
+    ];
+    case_3_3_rsv3_text: [frame(0x91, b"Hello")] => [closed(1002)];
+    case_3_4_rsv_all_text: [frame(0xf1, b"Hello")] => [closed(1002)];
+    case_3_5_rsv_binary: [frame(0xe2, &[0xff; 8])] => [closed(1002)];
+    case_3_6_rsv_ping: [frame(0xd9, b"Hello")] => [closed(1002)];
+    case_3_7_rsv_close: [frame(0xf8, &[0x03, 0xe8])] => [closed(1002)];
+}
+
+// 4. Opcodes
+cases! {
+    case_4_1_1_opcode_3: [frame(0x83, b"")] => [closed(1002)];
+    case_4_1_2_opcode_4_with_payload: [frame(0x84, b"reserved")] => [closed(1002)];
+    case_4_1_3_opcode_5_after_text: [text(b"Hello"), frame(0x85, b""), ping(b"")] => [
+        echo(0x81, b"Hello"),
+        closed(1002),
+    ];
+    case_4_1_5_opcode_7: [frame(0x87, b"")] => [closed(1002)];
+    case_4_2_1_opcode_11: [frame(0x8b, b"")] => [closed(1002)];
+    case_4_2_2_opcode_12_with_payload: [frame(0x8c, b"reserved")] => [closed(1002)];
+    case_4_2_5_opcode_15: [frame(0x8f, b"")] => [closed(1002)];
+}
+
+// 5. Fragmentation
+cases! {
+    case_5_1_fragmented_ping: [frame(0x09, b"frag"), frame(0x80, b"ment")] => [closed(1002)];
+    case_5_2_fragmented_pong: [frame(0x0a, b"frag"), frame(0x80, b"ment")] => [closed(1002)];
+    case_5_3_text_two_fragments: [frame(0x01, b"fragment1"), frame(0x80, b"fragment2")] => [
+        echo(0x81, b"fragment1fragment2"),
+    ];
+    case_5_4_text_three_fragments: [
+        frame(0x01, b"frag"),
+        frame(0x00, b"men"),
+        frame(0x80, b"ts"),
+    ] => [echo(0x81, b"fragments")];
+    case_5_5_text_fragments_chopped: [
+        chopped(frame(0x01, b"fragment1"), 1),
+        chopped(frame(0x80, b"fragment2"), 1),
+    ] => [echo(0x81, b"fragment1fragment2")];
+    case_5_6_ping_between_fragments: [
+        frame(0x01, b"fragment1"),
+        ping(b"ping"),
+        frame(0x80, b"fragment2"),
+    ] => [echo(0x8a, b"ping"), echo(0x81, b"fragment1fragment2")];
+    case_5_7_pings_between_fragments: [
+        frame(0x01, b"a"),
+        ping(b"1"),
+        frame(0x00, b"b"),
+        ping(b"2"),
+        frame(0x80, b"c"),
+    ] => [echo(0x8a, b"1"), echo(0x8a, b"2"), echo(0x81, b"abc")];
+    case_5_8_binary_fragments: [frame(0x02, &[1, 2]), frame(0x80, &[3])] => [
+        echo(0x82, &[1, 2, 3]),
+    ];
+    case_5_9_continuation_without_message: [frame(0x80, b"fragment")] => [closed(1002)];
+    case_5_10_unfinished_continuation_without_message: [frame(0x00, b"fragment")] => [
+        closed(1002),
+    ];
+    case_5_15_text_inside_fragments: [
+        frame(0x01, b"fragment1"),
+        frame(0x81, b"fragment2"),
+    ] => [closed(1002)];
+    case_5_16_two_message_starts: [frame(0x01, b"one"), frame(0x02, b"two")] => [closed(1002)];
+    case_5_17_empty_fragments: [frame(0x01, b""), frame(0x00, b""), frame(0x80, b"")] => [
+        echo(0x81, b""),
+    ];
+    case_5_18_message_after_fragments: [
+        frame(0x01, b"one"),
+        frame(0x80, b"two"),
+        text(b"three"),
+    ] => [echo(0x81, b"onetwo"), echo(0x81, b"three")];
+}
+
+// 6. UTF-8 handling
+cases! {
+    case_6_1_1_valid_text: [text("Hello-µ@ßöäüàá-UTF-8!!".as_bytes())] => [
+        echo(0x81, "Hello-µ@ßöäüàá-UTF-8!!".as_bytes()),
+    ];
+    case_6_1_2_four_byte_character: [text(&[0xf0, 0x90, 0x80, 0x80])] => [
+        echo(0x81, &[0xf0, 0x90, 0x80, 0x80]),
+    ];
+    case_6_1_3_max_code_point: [text(&[0xf4, 0x8f, 0xbf, 0xbf])] => [
+        echo(0x81, &[0xf4, 0x8f, 0xbf, 0xbf]),
+    ];
+    case_6_2_1_character_split_across_fragments: [
+        frame(0x01, &[0xce, 0xba, 0xe1]),
+        frame(0x00, &[0xbd]),
+        frame(0x80, &[0xb9, 0xcf, 0x83, 0xce, 0xbc, 0xce, 0xb5]),
+    ] => [echo(0x81, &[0xce, 0xba, 0xe1, 0xbd, 0xb9, 0xcf, 0x83, 0xce, 0xbc, 0xce, 0xb5])];
+    case_6_2_2_byte_per_fragment: [
+        frame(0x01, &[0xf0]),
+        frame(0x00, &[0x90]),
+        frame(0x00, &[0x80]),
+        frame(0x80, &[0x80]),
+    ] => [echo(0x81, &[0xf0, 0x90, 0x80, 0x80])];
+    case_6_3_1_invalid_in_text: [text(&[0xce, 0xba, 0xe1, 0xbd, 0xb9, 0xcf, 0x83, 0xce, 0xbc, 0xce, 0xb5, 0xed, 0xa0, 0x80, 0x65, 0x64, 0x69, 0x74, 0x65, 0x64])] => [
+        closed(1007),
+    ];
+    case_6_3_2_invalid_in_fragments: [
+        frame(0x01, &[0xce, 0xba, 0xe1, 0xbd]),
+        frame(0x80, &[0xb9, 0xed, 0xa0, 0x80]),
+    ] => [closed(1007)];
+    case_6_4_1_fail_fast_on_first_fragment: [frame(0x01, &[0x61, 0xff, 0x62])] => [
+        closed(1007),
+    ];
+    case_6_4_2_truncated_at_end: [text(&[0x61, 0xce])] => [closed(1007)];
+    case_6_4_3_truncated_at_last_fragment: [frame(0x01, &[0x61]), frame(0x80, &[0xce])] => [
+        closed(1007),
+    ];
+    case_6_5_1_overlong_slash: [text(&[0xc0, 0xaf])] => [closed(1007)];
+    case_6_5_2_overlong_three_bytes: [text(&[0xe0, 0x80, 0xaf])] => [closed(1007)];
+    case_6_6_1_surrogate: [text(&[0xed, 0xa0, 0x80])] => [closed(1007)];
+    case_6_6_2_surrogate_pair: [text(&[0xed, 0xa0, 0x80, 0xed, 0xb0, 0x80])] => [closed(1007)];
+    case_6_7_1_beyond_max_code_point: [text(&[0xf4, 0x90, 0x80, 0x80])] => [closed(1007)];
+    case_6_7_2_lone_continuation: [text(&[0x80])] => [closed(1007)];
+    case_6_7_3_invalid_byte: [text(&[0xfe])] => [closed(1007)];
+    case_6_8_1_binary_is_not_checked: [binary(&[0xff, 0xfe])] => [echo(0x82, &[0xff, 0xfe])];
+}
+
+// 7. Close handling
+cases! {
+    case_7_1_1_close_normal: [close(1000, b"")] => [closed(1000), Expect::Eof];
+    case_7_1_2_close_empty: [frame(0x88, b"")] => [Expect::Close(None), Expect::Eof];
+    case_7_1_3_text_after_close: [close(1000, b""), text(b"ignored")] => [
+        closed(1000),
+        Expect::Eof,
+    ];
+    case_7_1_4_text_then_close: [text(b"Hello"), close(1000, b"")] => [
+        echo(0x81, b"Hello"),
+        closed(1000),
+        Expect::Eof,
+    ];
+    case_7_1_5_close_during_fragments: [frame(0x01, b"fragment"), close(1000, b"")] => [
+        closed(1000),
+        Expect::Eof,
+    ];
+    case_7_3_1_close_one_byte: [frame(0x88, &[0x03])] => [closed(1002)];
+    case_7_3_2_close_with_reason: [close(1000, b"Goodbye")] => [closed(1000), Expect::Eof];
+    case_7_3_3_close_reason_123: [close(1000, &repeat(123))] => [closed(1000), Expect::Eof];
+    case_7_3_4_close_reason_124: [close(1000, &repeat(124))] => [closed(1002)];
+    case_7_5_1_close_invalid_reason: [close(1000, &[0xce, 0xba, 0xe1, 0xbd, 0xed, 0xa0, 0x80])] => [
+        closed(1007),
+    ];
+    case_7_7_1_code_1000: [close(1000, b"")] => [closed(1000)];
+    case_7_7_2_code_1001: [close(1001, b"")] => [closed(1001)];
+    case_7_7_3_code_1002: [close(1002, b"")] => [closed(1002)];
+    case_7_7_4_code_1003: [close(1003, b"")] => [closed(1003)];
+    case_7_7_5_code_1007: [close(1007, b"")] => [closed(1007)];
+    case_7_7_6_code_1008: [close(1008, b"")] => [closed(1008)];
+    case_7_7_7_code_1009: [close(1009, b"")] => [closed(1009)];
+    case_7_7_8_code_1010: [close(1010, b"")] => [closed(1010)];
+    case_7_7_9_code_1011: [close(1011, b"")] => [closed(1011)];
+    case_7_7_10_code_3000: [close(3000, b"")] => [closed(3000)];
+    case_7_7_11_code_3999: [close(3999, b"")] => [closed(3999)];
+    case_7_7_12_code_4000: [close(4000, b"")] => [closed(4000)];
+    case_7_7_13_code_4999: [close(4999, b"")] => [closed(4999)];
+    case_7_9_1_code_0: [close(0, b"")] => [closed(1002)];
+    case_7_9_2_code_999: [close(999, b"")] => [closed(1002)];
+    case_7_9_3_code_1004: [close(1004, b"")] => [closed(1002)];
+    case_7_9_4_code_1005: [close(1005, b"")] => [closed(1002)];
+    case_7_9_5_code_1006: [close(1006, b"")] => [closed(1002)];
+    case_7_9_6_code_1016: [close(1016, b"")] => [closed(1002)];
+    case_7_9_7_code_1100: [close(1100, b"")] => [closed(1002)];
+    case_7_9_8_code_2000: [close(2000, b"")] => [closed(1002)];
+    case_7_9_9_code_2999: [close(2999, b"")] => [closed(1002)];
+    case_7_13_1_code_5000: [close(5000, b"")] => [closed(1002)];
+}
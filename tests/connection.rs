@@ -0,0 +1,325 @@
+//! Connections used from tasks of their own while the server is reading from them, and set up by
+//! hand with settings of their own.
+mod common;
+
+use common::{frame, read_frame, Echo, Server};
+use quicksockets::{config::Config, frame::Opcode, prelude::*, Websocket};
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::{io::AsyncWriteExt, time};
+
+static SERVER: Server = Server::new("127.0.0.1:9008", |addr| {
+    common::serve(
+        Websocket::<TcpStream, _, _>::build(addr, |x| Script { conn: x })
+            .close_timeout(Duration::from_millis(300)),
+    )
+});
+static FRAGMENTS: Server = Server::new("127.0.0.1:9009", |addr| {
+    common::serve(Websocket::<TcpStream, _, _>::build(addr, Echo::new).fragment_size(4))
+});
+
+/// The codes connections were reported closed with, by the path they were opened on.
+static CLOSED: Mutex<Vec<(String, Option<CloseCode>)>> = Mutex::new(Vec::new());
+
+/// Does what the path it was opened on asks for, from a task of its own.
+struct Script {
+    conn: Connection<TcpStream>,
+}
+
+#[async_trait]
+impl SocketCallback for Script {
+    async fn on_open(&mut self) {
+        let mut conn = self.conn.clone();
+        let path = conn.request().path().to_string();
+
+        tokio::spawn(async move {
+            // By now the server is waiting for the client to send something.
+            time::delay_for(Duration::from_millis(100)).await;
+
+            match path.as_str() {
+                "/send" => {
+                    let _ = conn.send(Message::new("tick".into())).await;
+                }
+                "/writer" => {
+                    let mut writer = conn.writer(Opcode::Text).await.unwrap();
+                    writer.write(b"Hel").await.unwrap();
+                    writer.write(b"lo").await.unwrap();
+                    writer.finish().await.unwrap();
+                }
+                "/slow-writer" => {
+                    let mut writer = conn.writer(Opcode::Text).await.unwrap();
+                    writer.write(b"Hel").await.unwrap();
+                    // The client pings us in the meantime.
+                    time::delay_for(Duration::from_millis(200)).await;
+                    writer.write(b"lo").await.unwrap();
+                    writer.finish().await.unwrap();
+                }
+                "/dropped-writer" => {
+                    let mut writer = conn.writer(Opcode::Text).await.unwrap();
+                    writer.write(b"Hel").await.unwrap();
+                    drop(writer);
+
+                    let answer = match conn.send(Message::new("lo".into())).await {
+                        Ok(_) => "accepted",
+                        Err(_) => "refused",
+                    };
+
+                    let _ = conn.close(CloseCode::NormalClosure, answer).await;
+                }
+                "/closing-writer" => {
+                    let mut closer = conn.clone();
+                    tokio::spawn(async move {
+                        let _ = closer.close(CloseCode::NormalClosure, "").await;
+                    });
+
+                    // By now the Close frame is out and we are waiting for the answer.
+                    time::delay_for(Duration::from_millis(50)).await;
+
+                    if let Ok(mut writer) = conn.writer(Opcode::Text).await {
+                        let _ = writer.write(b"late").await;
+                        let _ = writer.finish().await;
+                    }
+                }
+                "/ping-writer" => {
+                    let answer = match conn.writer(Opcode::Ping).await {
+                        Ok(_) => "accepted",
+                        Err(_) => "refused",
+                    };
+
+                    let _ = conn.send(Message::new(answer.into())).await;
+                }
+                "/close" => {
+                    let _ = conn.close(CloseCode::GoingAway, "bye").await;
+                }
+                "/close-long" => {
+                    let _ = conn.close(CloseCode::NormalClosure, &"é".repeat(100)).await;
+                }
+                "/close-reserved" => {
+                    let answer = match conn.close(CloseCode::AbnormalClosure, "").await {
+                        Ok(_) => "accepted",
+                        Err(_) => "refused",
+                    };
+
+                    let _ = conn.send(Message::new(answer.into())).await;
+                }
+                _ => {}
+            }
+        });
+    }
+
+    async fn on_close(&mut self, code: Option<CloseCode>, _: String) {
+        let path = self.conn.request().path().to_string();
+        CLOSED.lock().unwrap().push((path, code));
+    }
+}
+
+/// Waits for the connection opened on `path` to be reported closed and returns every code it was
+/// reported with.
+async fn closed(path: &str) -> Vec<Option<CloseCode>> {
+    let reported = || {
+        CLOSED
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|x| x.0 == path)
+            .map(|x| x.1)
+            .collect::<Vec<_>>()
+    };
+
+    for _ in 0..50 {
+        if !reported().is_empty() {
+            break;
+        }
+
+        time::delay_for(Duration::from_millis(20)).await;
+    }
+
+    // Give a second report the chance to turn up as well.
+    time::delay_for(Duration::from_millis(100)).await;
+    reported()
+}
+
+#[tokio::test]
+async fn send_while_reading() {
+    let mut stream = SERVER.open("/send").await;
+    assert_eq!(
+        read_frame(&mut stream).await,
+        Some((0x81, b"tick".to_vec()))
+    );
+}
+
+#[tokio::test]
+async fn writer_while_reading() {
+    let mut stream = SERVER.open("/writer").await;
+
+    assert_eq!(read_frame(&mut stream).await, Some((0x01, b"Hel".to_vec())));
+    assert_eq!(read_frame(&mut stream).await, Some((0x00, b"lo".to_vec())));
+    assert_eq!(read_frame(&mut stream).await, Some((0x80, Vec::new())));
+}
+
+#[tokio::test]
+async fn pong_in_between_fragments() {
+    let mut stream = SERVER.open("/slow-writer").await;
+    assert_eq!(read_frame(&mut stream).await, Some((0x01, b"Hel".to_vec())));
+
+    stream.write_all(&frame(0x89, b"ping")).await.unwrap();
+    assert_eq!(
+        read_frame(&mut stream).await,
+        Some((0x8a, b"ping".to_vec()))
+    );
+
+    assert_eq!(read_frame(&mut stream).await, Some((0x00, b"lo".to_vec())));
+    assert_eq!(read_frame(&mut stream).await, Some((0x80, Vec::new())));
+}
+
+#[tokio::test]
+async fn writer_dropped_half_way() {
+    let mut stream = SERVER.open("/dropped-writer").await;
+    assert_eq!(read_frame(&mut stream).await, Some((0x01, b"Hel".to_vec())));
+
+    // No other message may start while the peer waits for the rest of this one.
+    assert_eq!(
+        read_frame(&mut stream).await,
+        Some((0x88, b"\x03\xe8refused".to_vec()))
+    );
+}
+
+#[tokio::test]
+async fn writer_after_close() {
+    let mut stream = SERVER.open("/closing-writer").await;
+    assert_eq!(
+        read_frame(&mut stream).await,
+        Some((0x88, b"\x03\xe8".to_vec()))
+    );
+
+    // Hung up on once the close timeout is up, without any more data.
+    assert_eq!(read_frame(&mut stream).await, None);
+}
+
+#[tokio::test]
+async fn writer_only_for_data() {
+    let mut stream = SERVER.open("/ping-writer").await;
+    assert_eq!(
+        read_frame(&mut stream).await,
+        Some((0x81, b"refused".to_vec()))
+    );
+}
+
+#[tokio::test]
+async fn close_while_reading() {
+    let mut stream = SERVER.open("/close").await;
+    assert_eq!(
+        read_frame(&mut stream).await,
+        Some((0x88, b"\x03\xe9bye".to_vec()))
+    );
+
+    // Hung up on as soon as the answer arrives.
+    stream.write_all(&frame(0x88, b"\x03\xe9")).await.unwrap();
+    let start = Instant::now();
+    assert_eq!(read_frame(&mut stream).await, None);
+    assert!(start.elapsed() < Duration::from_millis(250));
+}
+
+#[tokio::test]
+async fn close_without_answer() {
+    let mut stream = SERVER.open("/close").await;
+    let start = Instant::now();
+    assert_eq!(read_frame(&mut stream).await.unwrap().0, 0x88);
+
+    // Hung up on once the close timeout is up.
+    assert_eq!(read_frame(&mut stream).await, None);
+    assert!(start.elapsed() >= Duration::from_millis(250));
+}
+
+#[tokio::test]
+async fn close_reason_is_cut_short() {
+    let mut stream = SERVER.open("/close-long").await;
+    let (first, payload) = read_frame(&mut stream).await.unwrap();
+
+    assert_eq!(first, 0x88);
+    assert_eq!(payload.len(), 2 + 122);
+    assert_eq!(&payload[2..], "é".repeat(61).as_bytes());
+}
+
+#[tokio::test]
+async fn close_with_reserved_code() {
+    let mut stream = SERVER.open("/close-reserved").await;
+    assert_eq!(
+        read_frame(&mut stream).await,
+        Some((0x81, b"refused".to_vec()))
+    );
+}
+
+#[tokio::test]
+async fn protocol_error_reported_with_our_close_code() {
+    let mut stream = SERVER.open("/broken").await;
+
+    // RSV bits without an extension to claim them.
+    stream.write_all(&frame(0xf1, b"Hello")).await.unwrap();
+    assert_eq!(read_frame(&mut stream).await.unwrap().0, 0x88);
+    stream.write_all(&frame(0x88, b"\x03\xea")).await.unwrap();
+
+    assert_eq!(
+        closed("/broken").await,
+        vec![Some(CloseCode::ProtocolError)]
+    );
+}
+
+#[tokio::test]
+async fn io_error_reported_as_abnormal_closure() {
+    let stream = SERVER.open("/reset").await;
+
+    // Resets the connection instead of shutting it down.
+    stream.set_linger(Some(Duration::from_secs(0))).unwrap();
+    drop(stream);
+
+    assert_eq!(
+        closed("/reset").await,
+        vec![Some(CloseCode::AbnormalClosure)]
+    );
+}
+
+#[tokio::test]
+async fn fragment_size() {
+    let mut stream = FRAGMENTS.open("/").await;
+    stream
+        .write_all(&frame(0x81, b"Hello world"))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        read_frame(&mut stream).await,
+        Some((0x01, b"Hell".to_vec()))
+    );
+    assert_eq!(
+        read_frame(&mut stream).await,
+        Some((0x00, b"o wo".to_vec()))
+    );
+    assert_eq!(read_frame(&mut stream).await, Some((0x80, b"rld".to_vec())));
+
+    // Control frames are never split.
+    stream.write_all(&frame(0x89, b"ping!")).await.unwrap();
+    assert_eq!(
+        read_frame(&mut stream).await,
+        Some((0x8a, b"ping!".to_vec()))
+    );
+}
+
+#[tokio::test]
+async fn client_with_config() {
+    let stream = FRAGMENTS.connect().await;
+    let url = format!("ws://{}/", FRAGMENTS.addr());
+    let config = Config::default().max_message_size(8);
+    let mut conn = Connection::client_with_config(stream, &url, &config)
+        .await
+        .unwrap();
+
+    conn.send(Message::new("Hello world".into())).await.unwrap();
+
+    match conn.next().await {
+        Some(Err(Error::Protocol(CloseCode::MsgTooBig, _))) => {}
+        x => panic!("expected MsgTooBig, got {:?}", x.map(|x| x.map(|_| ()))),
+    }
+}
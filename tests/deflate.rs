@@ -0,0 +1,131 @@
+//! Negotiating permessage-deflate and compressed messages going through the server.
+mod common;
+
+use common::{frame, read_frame, Echo, Server};
+use flate2::{Decompress, FlushDecompress};
+use quicksockets::{deflate::DeflateConfig, prelude::*, Websocket};
+use tokio::io::AsyncWriteExt;
+
+static SERVER: Server = Server::new("127.0.0.1:9012", |addr| {
+    common::serve(
+        Websocket::<TcpStream, _, _>::build(addr, Echo::new).deflate(DeflateConfig {
+            server_no_context_takeover: true,
+            client_max_window_bits: Some(10),
+        }),
+    )
+});
+
+/// "Hello" compressed, twice in a row with the same context, from the examples in RFC 7692.
+const HELLO: &[u8] = &[0xf2, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+const HELLO_AGAIN: &[u8] = &[0xf2, 0x00, 0x11, 0x00, 0x00];
+/// "Hello" compressed into a block with BFINAL set, which ends the stream.
+const HELLO_FINAL: &[u8] = &[0xf3, 0x48, 0xcd, 0xc9, 0xc9, 0x07, 0x00];
+
+async fn accepted(extensions: &str) -> Option<String> {
+    SERVER.negotiate(extensions).await.1
+}
+
+fn inflate(data: &[u8]) -> Vec<u8> {
+    let mut input = data.to_vec();
+    input.extend_from_slice(&[0x00, 0x00, 0xff, 0xff]);
+
+    let mut out = Vec::with_capacity(1024);
+    Decompress::new(false)
+        .decompress_vec(&input, &mut out, FlushDecompress::Sync)
+        .unwrap();
+    out
+}
+
+#[tokio::test]
+async fn server_no_context_takeover() {
+    assert_eq!(
+        accepted("permessage-deflate").await.as_deref(),
+        Some("permessage-deflate; server_no_context_takeover")
+    );
+}
+
+#[tokio::test]
+async fn client_max_window_bits() {
+    assert_eq!(
+        accepted("permessage-deflate; client_max_window_bits")
+            .await
+            .as_deref(),
+        Some("permessage-deflate; server_no_context_takeover; client_max_window_bits=10")
+    );
+
+    // Clients asking for a smaller window than we would get what they asked for.
+    assert_eq!(
+        accepted("permessage-deflate; client_max_window_bits=9")
+            .await
+            .as_deref(),
+        Some("permessage-deflate; server_no_context_takeover; client_max_window_bits=9")
+    );
+}
+
+#[tokio::test]
+async fn declined_offers() {
+    // Our compressor always uses the biggest window.
+    assert_eq!(
+        accepted("permessage-deflate; server_max_window_bits=10").await,
+        None
+    );
+    assert_eq!(
+        accepted("permessage-deflate; client_max_window_bits=16").await,
+        None
+    );
+    assert_eq!(accepted("permessage-deflate; mystery").await, None);
+    assert_eq!(accepted("x-webkit-deflate-frame").await, None);
+
+    // The next offer is tried when one is declined.
+    let offers = "permessage-deflate; server_max_window_bits=10, \
+                  permessage-deflate; client_no_context_takeover";
+    assert_eq!(
+        accepted(offers).await.as_deref(),
+        Some("permessage-deflate; server_no_context_takeover; client_no_context_takeover")
+    );
+}
+
+#[tokio::test]
+async fn compressed_round_trip() {
+    let (mut stream, _) = SERVER.negotiate("permessage-deflate").await;
+
+    // The second message refers back to the first, the client keeps its context.
+    let mut data = frame(0xc1, HELLO);
+    data.extend(frame(0xc1, HELLO_AGAIN));
+    stream.write_all(&data).await.unwrap();
+
+    let (first, one) = read_frame(&mut stream).await.unwrap();
+    assert_eq!(first, 0xc1);
+    assert_eq!(inflate(&one), b"Hello");
+
+    // The server doesn't keep its context, so both answers come out the same.
+    let (first, two) = read_frame(&mut stream).await.unwrap();
+    assert_eq!(first, 0xc1);
+    assert_eq!(two, one);
+}
+
+#[tokio::test]
+async fn messages_ending_the_stream() {
+    let (mut stream, _) = SERVER.negotiate("permessage-deflate").await;
+
+    // The second message starts a new stream.
+    let mut data = frame(0xc1, HELLO_FINAL);
+    data.extend(frame(0xc1, HELLO_FINAL));
+    stream.write_all(&data).await.unwrap();
+
+    for _ in 0..2 {
+        let (first, payload) = read_frame(&mut stream).await.unwrap();
+        assert_eq!(first, 0xc1);
+        assert_eq!(inflate(&payload), b"Hello");
+    }
+}
+
+#[tokio::test]
+async fn uncompressed_messages_still_pass() {
+    let (mut stream, _) = SERVER.negotiate("permessage-deflate").await;
+    stream.write_all(&frame(0x81, b"plain")).await.unwrap();
+
+    let (first, payload) = read_frame(&mut stream).await.unwrap();
+    assert_eq!(first, 0xc1);
+    assert_eq!(inflate(&payload), b"plain");
+}
@@ -0,0 +1,121 @@
+//! Custom extensions claiming RSV bits next to permessage-deflate.
+mod common;
+
+use common::{frame, read_frame, Echo, Server};
+use quicksockets::{
+    deflate::DeflateConfig,
+    error::Error,
+    extension::{Extension, ExtensionOffer},
+    frame::Frame,
+    prelude::*,
+    Websocket,
+};
+use tokio::io::AsyncWriteExt;
+
+static SERVER: Server = Server::new("127.0.0.1:9013", |addr| {
+    common::serve(
+        Websocket::<TcpStream, _, _>::build(addr, Echo::new)
+            .deflate(DeflateConfig::default())
+            .extension(|| Reverse)
+            .extension(|| Conflict),
+    )
+});
+
+/// Sends the payload of every message backwards, marking it with RSV2.
+struct Reverse;
+
+impl Extension for Reverse {
+    fn name(&self) -> &str {
+        "x-reverse"
+    }
+
+    fn rsv_bits(&self) -> u8 {
+        0x20
+    }
+
+    fn negotiate(&mut self, _: &ExtensionOffer) -> Option<String> {
+        Some("x-reverse".into())
+    }
+
+    fn encode(&mut self, frame: &mut Frame) -> Result<(), std::io::Error> {
+        if !frame.opcode.is_control() {
+            let data: Vec<u8> = frame.payload().iter().rev().copied().collect();
+            frame.set_payload(data);
+            frame.set_rsv(frame.rsv() | 0x20);
+        }
+
+        Ok(())
+    }
+
+    fn decode(&mut self, frame: &mut Frame) -> Result<(), Error> {
+        if frame.rsv() & 0x20 != 0 {
+            let data: Vec<u8> = frame.payload().iter().rev().copied().collect();
+            frame.set_payload(data);
+        }
+
+        Ok(())
+    }
+}
+
+/// Wants the RSV1 bit permessage-deflate already uses.
+struct Conflict;
+
+impl Extension for Conflict {
+    fn name(&self) -> &str {
+        "x-conflict"
+    }
+
+    fn rsv_bits(&self) -> u8 {
+        0x40
+    }
+
+    fn negotiate(&mut self, _: &ExtensionOffer) -> Option<String> {
+        Some("x-conflict".into())
+    }
+}
+
+#[tokio::test]
+async fn rsv_bit_of_a_negotiated_extension() {
+    let (mut stream, accepted) = SERVER.negotiate("x-reverse").await;
+    assert_eq!(accepted.as_deref(), Some("x-reverse"));
+
+    stream.write_all(&frame(0xa1, b"olleH")).await.unwrap();
+    assert_eq!(
+        read_frame(&mut stream).await,
+        Some((0xa1, b"olleH".to_vec()))
+    );
+
+    // Frames without the bit are left alone on the way in.
+    stream.write_all(&frame(0x81, b"Hello")).await.unwrap();
+    assert_eq!(
+        read_frame(&mut stream).await,
+        Some((0xa1, b"olleH".to_vec()))
+    );
+}
+
+#[tokio::test]
+async fn rsv_bit_without_the_extension() {
+    let (mut stream, accepted) = SERVER.negotiate("permessage-deflate").await;
+    assert_eq!(accepted.as_deref(), Some("permessage-deflate"));
+
+    stream.write_all(&frame(0xa1, b"olleH")).await.unwrap();
+    let (first, payload) = read_frame(&mut stream).await.unwrap();
+
+    assert_eq!(first, 0x88);
+    assert_eq!(payload[..2], 1002u16.to_be_bytes());
+}
+
+#[tokio::test]
+async fn rsv_bit_already_taken() {
+    let (_, accepted) = SERVER.negotiate("x-conflict, permessage-deflate").await;
+    assert_eq!(accepted.as_deref(), Some("permessage-deflate"));
+
+    let (_, accepted) = SERVER.negotiate("x-conflict").await;
+    assert_eq!(accepted.as_deref(), Some("x-conflict"));
+}
+
+#[tokio::test]
+async fn several_extensions() {
+    let (_, accepted) = SERVER.negotiate("x-reverse, permessage-deflate").await;
+    assert_eq!(accepted.as_deref(), Some("permessage-deflate, x-reverse"));
+}
@@ -0,0 +1,127 @@
+//! Inputs which used to crash the server, kept so they never do again. New crashes found by the
+//! targets in `fuzz/` belong here once they are fixed.
+use bytes::BytesMut;
+use quicksockets::{
+    config::Config,
+    deflate::{Deflate, DeflateConfig},
+    error::Error,
+    extension::Extension,
+    frame::{CloseCode, Frame, WebsocketFrame},
+    handshake::Handshake,
+};
+use tokio_util::codec::{Decoder, Encoder};
+
+fn server(extensions: Vec<Box<dyn Extension>>) -> WebsocketFrame {
+    WebsocketFrame::with_config(&Config::default(), extensions, false)
+}
+
+/// Decodes `data` the way the decode target does, returning how many frames came out.
+fn decode_all(codec: &mut WebsocketFrame, data: &[u8]) -> usize {
+    let mut buf = BytesMut::from(data);
+    let mut frames = 0;
+
+    while let Ok(Some(_)) = codec.decode(&mut buf) {
+        frames += 1;
+    }
+
+    frames
+}
+
+#[test]
+fn handshake_without_key() {
+    let req = b"GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\r\n";
+    assert!(Handshake::parse(req).is_err());
+}
+
+#[test]
+fn handshake_garbage() {
+    assert!(Handshake::parse(b"").is_err());
+    assert!(Handshake::parse(b"\x00\xff\r\n\r\n").is_err());
+    assert!(Handshake::parse(b"GET / HTTP/1.1\r\nSec-WebSocket-Key").is_err());
+}
+
+#[test]
+fn handshake_many_headers() {
+    let mut req = b"GET / HTTP/1.1\r\nHost: localhost\r\n".to_vec();
+    for _ in 0..64 {
+        req.extend_from_slice(b"X: y\r\n");
+    }
+    req.extend_from_slice(b"Upgrade: websocket\r\nConnection: Upgrade\r\n");
+    req.extend_from_slice(b"Sec-WebSocket-Version: 13\r\n");
+    req.extend_from_slice(b"Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n");
+
+    assert_eq!(
+        Handshake::parse(&req).unwrap().key,
+        "dGhlIHNhbXBsZSBub25jZQ=="
+    );
+}
+
+#[test]
+fn handshake_invalid_utf8_key() {
+    let req = b"GET / HTTP/1.1\r\nSec-WebSocket-Key: \xfe\xff\r\n\r\n";
+    let _ = Handshake::parse(req);
+}
+
+#[test]
+fn truncated_extended_lengths() {
+    let mut codec = server(Vec::new());
+
+    assert_eq!(decode_all(&mut codec, &[0x81, 0xfe, 0x01]), 0);
+    assert_eq!(decode_all(&mut codec, &[0x81, 0xff, 0x00, 0x00, 0x00]), 0);
+    assert_eq!(decode_all(&mut codec, &[0x81, 0x85, 0x01, 0x02]), 0);
+}
+
+#[test]
+fn huge_length_without_a_frame_limit() {
+    let mut codec = WebsocketFrame::default();
+
+    // Used to reserve the whole declared length up front and abort on the allocation.
+    let mut header = vec![0x82, 0xff];
+    header.extend_from_slice(&(1u64 << 62).to_be_bytes());
+    header.extend_from_slice(&[1, 2, 3, 4]);
+
+    let mut buf = BytesMut::from(&header[..]);
+    let _ = codec.decode(&mut buf);
+    assert!(buf.capacity() < 1 << 30);
+
+    let mut header = vec![0x82, 0xff];
+    header.extend_from_slice(&u64::MAX.to_be_bytes());
+
+    assert_eq!(decode_all(&mut codec, &header), 0);
+}
+
+#[test]
+fn one_byte_close_payload() {
+    let mut codec = server(Vec::new());
+    assert_eq!(decode_all(&mut codec, &[0x88, 0x81, 0, 0, 0, 0, 0x03]), 0);
+}
+
+#[test]
+fn garbage_deflate_payload() {
+    let deflate = Deflate::new(DeflateConfig::default(), Some(1 << 20));
+    let mut codec = server(vec![Box::new(deflate)]);
+
+    let frame = [0xc1, 0x84, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff];
+    assert_eq!(decode_all(&mut codec, &frame), 0);
+}
+
+#[test]
+fn deflate_bomb() {
+    let deflate = Deflate::new(DeflateConfig::default(), None);
+    let mut client = WebsocketFrame::with_config(&Config::default(), vec![Box::new(deflate)], true);
+
+    // A megabyte of zeroes compresses down to about a kilobyte.
+    let mut buf = BytesMut::new();
+    client
+        .encode(Frame::binary(vec![0; 1 << 20]), &mut buf)
+        .unwrap();
+    assert!(buf.len() < 4096);
+
+    let deflate = Deflate::new(DeflateConfig::default(), Some(64 * 1024));
+    let mut codec = server(vec![Box::new(deflate)]);
+
+    match codec.decode(&mut buf) {
+        Err(Error::Protocol(CloseCode::MsgTooBig, _)) => {}
+        x => panic!("expected MsgTooBig, got {:?}", x.map(|_| ())),
+    }
+}
@@ -0,0 +1,217 @@
+//! The opening handshake as real clients send it: split over several packets, with big headers
+//! and with frames right behind it, as well as the HTTP errors invalid requests are answered with.
+mod common;
+
+use common::{read_all, send, Echo, Server};
+use quicksockets::{prelude::*, Websocket};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time,
+};
+
+static SERVER: Server = Server::new("127.0.0.1:9002", |addr| {
+    common::serve(
+        Websocket::<TcpStream, _, _>::build(addr, Echo::new)
+            .max_handshake_size(16 << 10)
+            .handshake_timeout(Duration::from_millis(500))
+            .protocols(&["chat", "superchat"]),
+    )
+});
+
+/// A masked text frame saying "Hello".
+const HELLO: &[u8] = &[
+    0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+];
+
+/// Builds a request with `extra` headers added before the empty line.
+fn request(extra: &str) -> Vec<u8> {
+    common::request("/", extra).into_bytes()
+}
+
+fn accepted(response: &[u8]) -> bool {
+    response.starts_with(b"HTTP/1.1 101")
+}
+
+#[tokio::test]
+async fn request_split_across_packets() {
+    let mut stream = SERVER.connect().await;
+
+    for chunk in request("").chunks(7) {
+        stream.write_all(chunk).await.unwrap();
+        time::delay_for(Duration::from_millis(2)).await;
+    }
+
+    assert!(accepted(&read_all(&mut stream).await));
+}
+
+#[tokio::test]
+async fn big_headers() {
+    let mut stream = SERVER.connect().await;
+    let cookie = format!("Cookie: session={}\r\n", "a".repeat(8000));
+
+    stream.write_all(&request(&cookie)).await.unwrap();
+    assert!(accepted(&read_all(&mut stream).await));
+}
+
+#[tokio::test]
+async fn many_headers() {
+    let mut stream = SERVER.connect().await;
+    let headers: String = (0..100)
+        .map(|i| format!("X-Header-{}: {}\r\n", i, i))
+        .collect();
+
+    stream.write_all(&request(&headers)).await.unwrap();
+    assert!(accepted(&read_all(&mut stream).await));
+}
+
+#[tokio::test]
+async fn frame_right_behind_request() {
+    let mut stream = SERVER.connect().await;
+    let mut req = request("");
+    req.extend_from_slice(HELLO);
+
+    stream.write_all(&req).await.unwrap();
+
+    let response = read_all(&mut stream).await;
+    assert!(accepted(&response));
+    assert!(response.ends_with(b"\x81\x05Hello"));
+}
+
+#[tokio::test]
+async fn handshake_over_the_limit() {
+    let mut stream = SERVER.connect().await;
+    let cookie = format!("Cookie: session={}\r\n", "a".repeat(20000));
+
+    send(&mut stream, &request(&cookie)).await;
+    assert!(read_all(&mut stream).await.starts_with(b"HTTP/1.1 431"));
+}
+
+#[tokio::test]
+async fn valid_request() {
+    assert_eq!(SERVER.status(request("")).await, 101);
+}
+
+#[tokio::test]
+async fn plain_http_request() {
+    let req = b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n";
+    assert_eq!(SERVER.status(req).await, 400);
+}
+
+#[tokio::test]
+async fn not_http() {
+    assert_eq!(
+        SERVER.status(b"\x16\x03\x01\x02\x00\x01\r\n\r\n").await,
+        400
+    );
+}
+
+#[tokio::test]
+async fn post_request() {
+    let req = String::from_utf8_lossy(&request("")).replacen("GET", "POST", 1);
+    assert_eq!(SERVER.status(req.as_bytes()).await, 405);
+}
+
+#[tokio::test]
+async fn http_1_0() {
+    let req = String::from_utf8_lossy(&request("")).replacen("HTTP/1.1", "HTTP/1.0", 1);
+    assert_eq!(SERVER.status(req.as_bytes()).await, 400);
+}
+
+#[tokio::test]
+async fn missing_connection_upgrade() {
+    let req = String::from_utf8_lossy(&request("")).replace("Connection: Upgrade\r\n", "");
+    assert_eq!(SERVER.status(req.as_bytes()).await, 400);
+}
+
+#[tokio::test]
+async fn connection_with_several_tokens() {
+    let req = String::from_utf8_lossy(&request(""))
+        .replace("Connection: Upgrade", "Connection: keep-alive, Upgrade");
+    assert_eq!(SERVER.status(req.as_bytes()).await, 101);
+}
+
+#[tokio::test]
+async fn old_version() {
+    let req = String::from_utf8_lossy(&request(""))
+        .replace("Sec-WebSocket-Version: 13", "Sec-WebSocket-Version: 8");
+
+    let mut stream = SERVER.connect().await;
+    stream.write_all(req.as_bytes()).await.unwrap();
+    let response = read_all(&mut stream).await;
+    let response = String::from_utf8_lossy(&response);
+
+    assert!(response.starts_with("HTTP/1.1 426"));
+    assert!(response.contains("Sec-WebSocket-Version: 13\r\n"));
+}
+
+#[tokio::test]
+async fn short_key() {
+    let req = String::from_utf8_lossy(&request("")).replace("dGhlIHNhbXBsZSBub25jZQ==", "c2hvcnQ=");
+    assert_eq!(SERVER.status(req.as_bytes()).await, 400);
+}
+
+#[tokio::test]
+async fn missing_key() {
+    let req = String::from_utf8_lossy(&request(""))
+        .replace("Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n", "");
+    assert_eq!(SERVER.status(req.as_bytes()).await, 400);
+}
+
+/// Sends `req` and returns the `Sec-WebSocket-Protocol` the server answered with.
+async fn protocol(req: &[u8]) -> Option<String> {
+    let mut stream = SERVER.connect().await;
+    stream.write_all(req).await.unwrap();
+
+    let response = read_all(&mut stream).await;
+    let response = String::from_utf8_lossy(&response);
+    assert!(response.starts_with("HTTP/1.1 101"));
+
+    response
+        .lines()
+        .find(|x| x.starts_with("Sec-WebSocket-Protocol: "))
+        .map(|x| x["Sec-WebSocket-Protocol: ".len()..].to_string())
+}
+
+#[tokio::test]
+async fn server_preferred_protocol() {
+    let req = request("Sec-WebSocket-Protocol: superchat, chat\r\n");
+    assert_eq!(protocol(&req).await.as_deref(), Some("chat"));
+}
+
+#[tokio::test]
+async fn protocol_over_several_headers() {
+    let req =
+        request("Sec-WebSocket-Protocol: v2.example\r\nSec-WebSocket-Protocol: superchat\r\n");
+    assert_eq!(protocol(&req).await.as_deref(), Some("superchat"));
+}
+
+#[tokio::test]
+async fn no_common_protocol() {
+    let req = request("Sec-WebSocket-Protocol: v2.example\r\n");
+    assert_eq!(protocol(&req).await, None);
+}
+
+#[tokio::test]
+async fn no_protocol_offered() {
+    assert_eq!(protocol(&request("")).await, None);
+}
+
+#[tokio::test]
+async fn stalled_client_does_not_block_others() {
+    let mut stalled = SERVER.connect().await;
+    stalled.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+
+    let other = time::timeout(Duration::from_secs(2), SERVER.status(request(""))).await;
+    assert_eq!(other.expect("the stalled client blocked the server"), 101);
+}
+
+#[tokio::test]
+async fn stalled_client_is_dropped() {
+    let mut stalled = SERVER.connect().await;
+    stalled.write_all(b"GET / HTTP/1.1\r\n").await.unwrap();
+
+    let mut buf = [0; 16];
+    let read = time::timeout(Duration::from_secs(2), stalled.read(&mut buf)).await;
+    assert_eq!(read.expect("the stalled client wasn't dropped").unwrap(), 0);
+}
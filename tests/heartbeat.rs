@@ -0,0 +1,81 @@
+//! Clients which stop answering pings or hang up without a closing handshake.
+mod common;
+
+use common::{frame, read_frame, Server};
+use quicksockets::{prelude::*, Websocket};
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use tokio::{io::AsyncWriteExt, time};
+
+static SERVER: Server = Server::new("127.0.0.1:9010", |addr| {
+    common::serve(
+        Websocket::<TcpStream, _, _>::build(addr, |x| Count { conn: x })
+            .heartbeat(Duration::from_millis(100), Duration::from_millis(100)),
+    )
+});
+
+/// How many connections were reported closed abnormally, by the path they were opened on.
+static DROPPED: AtomicUsize = AtomicUsize::new(0);
+static HUNG_UP: AtomicUsize = AtomicUsize::new(0);
+
+/// Counts the connections closed without a closing handshake.
+struct Count {
+    conn: Connection<TcpStream>,
+}
+
+#[async_trait]
+impl SocketCallback for Count {
+    async fn on_close(&mut self, code: Option<CloseCode>, _: String) {
+        if code != Some(CloseCode::AbnormalClosure) {
+            return;
+        }
+
+        match self.conn.request().path() {
+            "/dropped" => DROPPED.fetch_add(1, Ordering::SeqCst),
+            "/hung-up" => HUNG_UP.fetch_add(1, Ordering::SeqCst),
+            _ => 0,
+        };
+    }
+}
+
+/// Waits a little for `counter` to go up.
+async fn reported(counter: &AtomicUsize) -> bool {
+    for _ in 0..50 {
+        if counter.load(Ordering::SeqCst) > 0 {
+            return true;
+        }
+
+        time::delay_for(Duration::from_millis(20)).await;
+    }
+
+    false
+}
+
+#[tokio::test]
+async fn answered_pings_keep_the_connection() {
+    let mut stream = SERVER.open("/").await;
+
+    for _ in 0..3 {
+        assert_eq!(read_frame(&mut stream).await, Some((0x89, Vec::new())));
+        stream.write_all(&frame(0x8a, b"")).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn unanswered_ping_drops_the_connection() {
+    let mut stream = SERVER.open("/dropped").await;
+
+    assert_eq!(read_frame(&mut stream).await, Some((0x89, Vec::new())));
+    assert_eq!(read_frame(&mut stream).await, None);
+    assert!(reported(&DROPPED).await, "on_close wasn't called");
+}
+
+#[tokio::test]
+async fn client_hanging_up() {
+    let stream = SERVER.open("/hung-up").await;
+    drop(stream);
+
+    assert!(reported(&HUNG_UP).await, "on_close wasn't called");
+}
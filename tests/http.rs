@@ -0,0 +1,153 @@
+//! Plain HTTP requests answered on the same port as the websocket server.
+mod common;
+
+use common::{Echo, Server};
+use quicksockets::{
+    http::{Health, HttpHandler, StaticFile},
+    prelude::*,
+    Request, Response, Websocket,
+};
+use std::time::Duration;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    time,
+};
+
+static SERVER: Server = Server::new("127.0.0.1:9007", |addr| {
+    let page = std::env::temp_dir().join("quicksockets-http-test.html");
+    std::fs::write(&page, PAGE).unwrap();
+
+    common::serve(
+        Websocket::<TcpStream, _, _>::build(addr, Echo::new)
+            .http(Health::default())
+            .http(Hello)
+            .http(StaticFile::new("/", page))
+            .http(StaticFile::new("/gone", "/does/not/exist.html"))
+            .handshake_timeout(Duration::from_millis(500)),
+    )
+});
+
+const PAGE: &str = "<!doctype html><title>test page</title>";
+
+/// Greets whoever is named in the query string of `/hello`.
+struct Hello;
+
+#[async_trait]
+impl HttpHandler for Hello {
+    async fn handle(&self, request: &Request) -> Option<Response> {
+        if request.path() == "/slow" {
+            time::delay_for(Duration::from_secs(5)).await;
+        }
+
+        if request.path() == "/late" {
+            time::delay_for(Duration::from_secs(1)).await;
+            return Some(Response::new(200).body("late"));
+        }
+
+        if request.path() != "/hello" {
+            return None;
+        }
+
+        let name = request.query_param("name").unwrap_or("stranger");
+        Some(Response::new(200).body(format!("hello {}", name)))
+    }
+}
+
+/// Sends `req` and reads the response until the server hangs up.
+async fn send(req: &str) -> String {
+    let mut stream = SERVER.connect().await;
+    stream.write_all(req.as_bytes()).await.unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+async fn get(path: &str) -> String {
+    send(&format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path)).await
+}
+
+#[tokio::test]
+async fn health() {
+    let response = get("/health").await;
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("\r\nConnection: close\r\n"));
+    assert!(response.ends_with("\r\n\r\nOK"));
+}
+
+#[tokio::test]
+async fn head_has_no_body() {
+    let response = send("HEAD /health HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("\r\nContent-Length: 2\r\n"));
+    assert!(response.ends_with("\r\n\r\n"));
+}
+
+#[tokio::test]
+async fn static_file() {
+    let response = get("/").await;
+
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("\r\nContent-Type: text/html; charset=utf-8\r\n"));
+    assert!(response.ends_with(PAGE));
+}
+
+#[tokio::test]
+async fn missing_file() {
+    assert!(get("/gone").await.starts_with("HTTP/1.1 500"));
+}
+
+#[tokio::test]
+async fn custom_handler() {
+    assert!(get("/hello?name=ferris").await.ends_with("hello ferris"));
+}
+
+#[tokio::test]
+async fn unknown_path() {
+    assert!(get("/nope").await.starts_with("HTTP/1.1 404"));
+}
+
+#[tokio::test]
+async fn other_method() {
+    let response = send("POST /health HTTP/1.1\r\nHost: localhost\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 404"));
+}
+
+#[tokio::test]
+async fn upgrade_still_works() {
+    let mut stream = SERVER.connect().await;
+    let req = "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+        Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+    stream.write_all(req.as_bytes()).await.unwrap();
+
+    let mut buf = [0; 1024];
+    let n = stream.read(&mut buf).await.unwrap();
+    assert!(buf[..n].starts_with(b"HTTP/1.1 101"));
+}
+
+#[tokio::test]
+async fn broken_upgrade_is_not_served() {
+    let req = "GET / HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+        Sec-WebSocket-Version: 13\r\n\r\n";
+    assert!(send(req).await.starts_with("HTTP/1.1 400"));
+}
+
+#[tokio::test]
+async fn slow_handler_does_not_hold_up_others() {
+    let slow = tokio::spawn(get("/slow"));
+    time::delay_for(Duration::from_millis(50)).await;
+
+    let health = time::timeout(Duration::from_secs(2), get("/health")).await;
+    assert!(health
+        .expect("a slow handler held up other clients")
+        .starts_with("HTTP/1.1 200"));
+
+    drop(slow);
+}
+
+#[tokio::test]
+async fn handler_slower_than_the_handshake_timeout() {
+    assert!(get("/late").await.ends_with("\r\n\r\nlate"));
+}
@@ -0,0 +1,86 @@
+//! Frames and messages bigger than the server is willing to take.
+mod common;
+
+use common::{frame, read_frame, send, Echo, Server, KEY};
+use quicksockets::{prelude::*, Websocket};
+use tokio::io::AsyncWriteExt;
+
+static SERVER: Server = Server::new("127.0.0.1:9011", |addr| {
+    common::serve(
+        Websocket::<TcpStream, _, _>::build(addr, Echo::new)
+            .max_frame_size(64)
+            .max_message_size(100),
+    )
+});
+
+/// Sends `data` and returns the status code of the Close frame the server answers with.
+async fn close_code(data: &[u8]) -> Option<u16> {
+    let mut stream = SERVER.open("/").await;
+    send(&mut stream, data).await;
+
+    match read_frame(&mut stream).await {
+        Some((0x88, payload)) => Some(u16::from_be_bytes([payload[0], payload[1]])),
+        x => panic!("expected a Close frame, got {:?}", x),
+    }
+}
+
+/// The header of a masked binary frame with a 64-bit `length`, without any payload.
+fn header(length: u64) -> Vec<u8> {
+    let mut out = vec![0x82, 0xff];
+    out.extend_from_slice(&length.to_be_bytes());
+    out.extend_from_slice(&KEY);
+    out
+}
+
+#[tokio::test]
+async fn within_limits() {
+    let mut stream = SERVER.open("/").await;
+
+    let mut data = frame(0x02, &[1; 64]);
+    data.extend(frame(0x80, &[2; 36]));
+    stream.write_all(&data).await.unwrap();
+
+    let mut expected = vec![1; 64];
+    expected.extend_from_slice(&[2; 36]);
+    assert_eq!(read_frame(&mut stream).await, Some((0x82, expected)));
+}
+
+#[tokio::test]
+async fn frame_too_big() {
+    assert_eq!(close_code(&frame(0x82, &[0; 65])).await, Some(1009));
+}
+
+#[tokio::test]
+async fn frame_too_big_from_the_header_alone() {
+    assert_eq!(close_code(&header(65)).await, Some(1009));
+    assert_eq!(close_code(&header(i64::MAX as u64)).await, Some(1009));
+}
+
+#[tokio::test]
+async fn length_with_the_most_significant_bit_set() {
+    assert_eq!(close_code(&header(u64::MAX)).await, Some(1002));
+    assert_eq!(close_code(&header(1 << 63)).await, Some(1002));
+}
+
+#[tokio::test]
+async fn message_too_big() {
+    // Refused as soon as the fragments add up to more than the limit, without waiting for the
+    // rest of the message.
+    let mut data = frame(0x01, &[b'a'; 60]);
+    data.extend(frame(0x00, &[b'a'; 60]));
+
+    assert_eq!(close_code(&data).await, Some(1009));
+}
+
+#[tokio::test]
+async fn control_frames_between_fragments_do_not_count() {
+    let mut stream = SERVER.open("/").await;
+
+    let mut data = frame(0x01, &[b'a'; 60]);
+    data.extend(frame(0x89, &[b'p'; 60]));
+    data.extend(frame(0x80, &[b'a'; 40]));
+    stream.write_all(&data).await.unwrap();
+
+    assert_eq!(read_frame(&mut stream).await, Some((0x8a, vec![b'p'; 60])));
+    assert_eq!(read_frame(&mut stream).await, Some((0x81, vec![b'a'; 100])));
+}
@@ -0,0 +1,81 @@
+//! Word at a time masking checked against masking one byte at a time, for every alignment the
+//! data can have in memory and every position it can have within the payload.
+use quicksockets::frame::WebsocketFrame;
+
+const KEY: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+/// Masks `data` one byte at a time, as if it started `offset` bytes into the payload.
+fn naive(data: &[u8], key: [u8; 4], offset: usize) -> Vec<u8> {
+    data.iter()
+        .enumerate()
+        .map(|(i, b)| b ^ key[(offset + i) % 4])
+        .collect()
+}
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|x| (x * 7) as u8).collect()
+}
+
+#[test]
+fn unaligned_heads_and_tails() {
+    let data = payload(100);
+
+    // Slicing into the buffer moves the start and end of the data around the word boundaries.
+    for start in 0..16 {
+        for end in start..data.len() {
+            let mut buf = data.clone();
+            WebsocketFrame::mutate_in_place(&mut buf[start..end], KEY, 0);
+
+            assert_eq!(buf[start..end], naive(&data[start..end], KEY, 0)[..]);
+            assert_eq!(buf[..start], data[..start]);
+            assert_eq!(buf[end..], data[end..]);
+        }
+    }
+}
+
+#[test]
+fn offsets() {
+    let data = payload(100);
+
+    for offset in 0..12 {
+        for start in 0..8 {
+            let mut buf = data.clone();
+            WebsocketFrame::mutate_in_place(&mut buf[start..], KEY, offset);
+
+            assert_eq!(buf[start..], naive(&data[start..], KEY, offset)[..]);
+        }
+    }
+}
+
+#[test]
+fn chunk_by_chunk() {
+    let data = payload(1000);
+
+    for chunk in &[1, 3, 5, 8, 13, 64, 333] {
+        let mut buf = data.clone();
+
+        for (i, x) in buf.chunks_mut(*chunk).enumerate() {
+            WebsocketFrame::mutate_in_place(x, KEY, i * chunk);
+        }
+
+        assert_eq!(buf, naive(&data, KEY, 0));
+    }
+}
+
+#[test]
+fn masking_twice_restores_the_data() {
+    let data = payload(64 * 1024 + 3);
+    let mut buf = data.clone();
+
+    WebsocketFrame::mutate_in_place(&mut buf[3..], KEY, 3);
+    assert_ne!(buf, data);
+
+    WebsocketFrame::mutate_in_place(&mut buf[3..], KEY, 3);
+    assert_eq!(buf, data);
+}
+
+#[test]
+fn mutate() {
+    let data = payload(100);
+    assert_eq!(WebsocketFrame::mutate(&data, &KEY), naive(&data, KEY, 0));
+}
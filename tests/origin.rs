@@ -0,0 +1,47 @@
+//! Upgrades refused for origins outside of the allowlist.
+mod common;
+
+use common::{request, Echo, Server};
+use quicksockets::{prelude::*, Websocket};
+
+static SERVER: Server = Server::new("127.0.0.1:9006", |addr| {
+    common::serve(
+        Websocket::<TcpStream, _, _>::build(addr, Echo::new)
+            .allowed_origins(&["https://example.com", "https://*.Example.org"]),
+    )
+});
+
+/// Returns the status code an upgrade request from `origin` is answered with.
+async fn from_origin(origin: &str) -> u16 {
+    let extra = format!("Origin: {}\r\n", origin);
+    SERVER.status(request("/", &extra)).await
+}
+
+#[tokio::test]
+async fn exact_origin() {
+    assert_eq!(from_origin("https://example.com").await, 101);
+    assert_eq!(from_origin("HTTPS://EXAMPLE.COM").await, 101);
+}
+
+#[tokio::test]
+async fn other_origin() {
+    assert_eq!(from_origin("https://evil.com").await, 403);
+    assert_eq!(from_origin("http://example.com").await, 403);
+    assert_eq!(from_origin("https://example.com.evil.com").await, 403);
+    assert_eq!(from_origin("https://example.com:8443").await, 403);
+    assert_eq!(from_origin("null").await, 403);
+}
+
+#[tokio::test]
+async fn wildcard_subdomain() {
+    assert_eq!(from_origin("https://app.example.org").await, 101);
+    assert_eq!(from_origin("https://a.b.example.org").await, 101);
+    assert_eq!(from_origin("https://example.org").await, 403);
+    assert_eq!(from_origin("https://evilexample.org").await, 403);
+    assert_eq!(from_origin("https://evil.com/.example.org").await, 403);
+}
+
+#[tokio::test]
+async fn without_origin() {
+    assert_eq!(SERVER.status(request("/", "")).await, 101);
+}
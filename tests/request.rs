@@ -0,0 +1,104 @@
+//! Details of the upgrade request made available to handlers.
+mod common;
+
+use common::Server;
+use quicksockets::{prelude::*, Websocket};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+static SERVER: Server = Server::new("127.0.0.1:9004", |addr| {
+    common::serve(Websocket::<TcpStream, _, _>::build(addr, |x| Inspect {
+        conn: x,
+    }))
+});
+
+/// Answers a question about the request, asked in the `q` query parameter, once it is open.
+struct Inspect {
+    conn: Connection<TcpStream>,
+}
+
+#[async_trait]
+impl SocketCallback for Inspect {
+    async fn on_open(&mut self) {
+        let req = self.conn.request();
+
+        let answer = match req.query_param("q").unwrap_or_default() {
+            "peer" => req.peer_addr().map(|x| x.ip().to_string()),
+            "path" => Some(req.path().to_string()),
+            "query" => Some(req.query().to_string()),
+            "param" => req.query_param("name").map(String::from),
+            "params" => Some(req.query_params().len().to_string()),
+            "header" => req.header("x-tenant").map(String::from),
+            "cookie" => req.cookie("session").map(String::from),
+            "cookies" => Some(req.cookies().len().to_string()),
+            _ => None,
+        };
+
+        let answer = answer.unwrap_or_else(|| "none".into());
+        let _ = self.conn.send(Message::new(answer)).await;
+    }
+
+    async fn on_close(&mut self, _: Option<CloseCode>, _: String) {}
+}
+
+/// Opens a connection on `target` with `extra` headers and returns the server's first message.
+async fn ask(target: &str, extra: &str) -> String {
+    let mut stream = SERVER.connect().await;
+    let req = common::request(target, extra);
+    stream.write_all(req.as_bytes()).await.unwrap();
+
+    let mut response = Vec::new();
+    let mut buf = [0; 1024];
+
+    // The answer is a single short text frame right behind the response head.
+    loop {
+        let n = stream.read(&mut buf).await.unwrap();
+        assert!(n > 0, "the server hung up");
+        response.extend_from_slice(&buf[..n]);
+
+        if let Some(i) = response.windows(4).position(|x| x == b"\r\n\r\n") {
+            let frame = &response[i + 4..];
+
+            if frame.len() >= 2 && frame.len() >= 2 + frame[1] as usize {
+                return String::from_utf8_lossy(&frame[2..]).into_owned();
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn peer_addr() {
+    assert_eq!(ask("/?q=peer", "").await, "127.0.0.1");
+}
+
+#[tokio::test]
+async fn path() {
+    assert_eq!(ask("/rooms/1?q=path", "").await, "/rooms/1");
+}
+
+#[tokio::test]
+async fn query() {
+    assert_eq!(ask("/?q=query&a=1", "").await, "q=query&a=1");
+    assert_eq!(ask("/?q=params&a=1&b&c=", "").await, "4");
+}
+
+#[tokio::test]
+async fn query_param_is_decoded() {
+    assert_eq!(ask("/?q=param&name=J%C3%BCrgen+M", "").await, "Jürgen M");
+    assert_eq!(ask("/?q=param&name=100%", "").await, "100%");
+    assert_eq!(ask("/?q=param", "").await, "none");
+}
+
+#[tokio::test]
+async fn header() {
+    assert_eq!(ask("/?q=header", "X-Tenant: acme\r\n").await, "acme");
+    assert_eq!(ask("/?q=header", "").await, "none");
+}
+
+#[tokio::test]
+async fn cookies() {
+    let cookies = "Cookie: theme=dark; session=abc123\r\nCookie: lang=en\r\n";
+
+    assert_eq!(ask("/?q=cookie", cookies).await, "abc123");
+    assert_eq!(ask("/?q=cookies", cookies).await, "3");
+    assert_eq!(ask("/?q=cookie", "").await, "none");
+}
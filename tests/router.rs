@@ -0,0 +1,128 @@
+//! Connections handed to different handlers depending on the path they are opened on.
+mod common;
+
+use common::Server;
+use quicksockets::{
+    prelude::*,
+    router::{Route, Router},
+    Websocket,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+static SERVER: Server = Server::new("127.0.0.1:9003", |addr| {
+    let router = Router::new()
+        .route("/", |conn| Greet {
+            conn,
+            greeting: |_| "root".into(),
+        })
+        .route("/chat/:room", |conn| Greet {
+            conn,
+            greeting: |x| format!("chat {}", x.param("room").unwrap()),
+        })
+        .route("/chat/:room/:user", |conn| Greet {
+            conn,
+            greeting: |x| {
+                format!(
+                    "{} in {}",
+                    x.param("user").unwrap(),
+                    x.param("room").unwrap()
+                )
+            },
+        })
+        .route("/files/*path", |conn| Greet {
+            conn,
+            greeting: |x| format!("file {}", x.param("path").unwrap()),
+        });
+
+    common::serve(Websocket::<TcpStream, _, _>::with_router(addr, router))
+});
+
+/// Greets every client with what it was routed on.
+struct Greet {
+    conn: Connection<TcpStream>,
+    greeting: fn(&Connection<TcpStream>) -> String,
+}
+
+#[async_trait]
+impl SocketCallback for Greet {
+    async fn on_open(&mut self) {
+        let greeting = (self.greeting)(&self.conn);
+        let _ = self.conn.send(Message::new(greeting)).await;
+    }
+
+    async fn on_close(&mut self, _: Option<CloseCode>, _: String) {}
+}
+
+/// Opens a connection on `path` and returns the first message the server sends.
+async fn greeting(path: &str) -> String {
+    SERVER.connect().await;
+
+    let url = format!("ws://{}{}", SERVER.addr(), path);
+    let mut conn = Connection::<TcpStream>::connect(&url).await.unwrap();
+    conn.next().await.unwrap().unwrap().get_msg()
+}
+
+#[tokio::test]
+async fn root() {
+    assert_eq!(greeting("/").await, "root");
+}
+
+#[tokio::test]
+async fn parameter() {
+    assert_eq!(greeting("/chat/rust").await, "chat rust");
+}
+
+#[tokio::test]
+async fn several_parameters() {
+    assert_eq!(greeting("/chat/rust/ferris").await, "ferris in rust");
+}
+
+#[tokio::test]
+async fn query_string_is_ignored() {
+    assert_eq!(greeting("/chat/rust?token=abc").await, "chat rust");
+}
+
+#[tokio::test]
+async fn wildcard() {
+    assert_eq!(greeting("/files/a/b/c.txt").await, "file a/b/c.txt");
+}
+
+#[tokio::test]
+async fn unknown_path() {
+    let mut stream = SERVER.connect().await;
+    let req = common::request("/nope", "");
+    stream.write_all(req.as_bytes()).await.unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await.unwrap();
+    assert!(response.starts_with(b"HTTP/1.1 404"));
+}
+
+#[test]
+fn route_matching() {
+    let route = Route::new("/chat/:room");
+    let params = route.matches("/chat/rust").unwrap();
+
+    assert_eq!(params["room"], "rust");
+    assert!(route.matches("/chat/rust/").is_some());
+    assert!(route.matches("/chat").is_none());
+    assert!(route.matches("/chat/rust/ferris").is_none());
+    assert!(route.matches("/other/rust").is_none());
+}
+
+#[test]
+fn wildcard_matching() {
+    let route = Route::new("/static/*");
+
+    assert!(route.matches("/static").is_some());
+    assert!(route.matches("/static/css/site.css").is_some());
+    assert!(route.matches("/other").is_none());
+    assert!(Route::new("/").matches("/").is_some());
+    assert!(Route::new("/").matches("/x").is_none());
+}
+
+#[test]
+#[should_panic]
+fn wildcard_in_the_middle() {
+    Route::new("/files/*/x");
+}